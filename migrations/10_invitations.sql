@@ -0,0 +1,21 @@
+-- An invitation is for a specific email, or for whoever redeems its code
+-- (only the hash of the code is stored). Either way it is used once.
+CREATE TABLE IF NOT EXISTS invitations
+(
+    id          UUID PRIMARY KEY,
+    email       TEXT,
+    code_hash   TEXT UNIQUE,
+    role        TEXT NOT NULL,
+    invited_by  UUID
+        REFERENCES argent_users
+        ON DELETE SET NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at  TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    accepted_by UUID
+        REFERENCES argent_users
+        ON DELETE SET NULL,
+    CHECK (email IS NOT NULL OR code_hash IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS invitations_email ON invitations (lower(email));
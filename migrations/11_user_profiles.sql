@@ -0,0 +1,21 @@
+-- Preferences are a JSON document so that new ones do not need a migration,
+-- missing keys fall back to the defaults in the api
+CREATE TABLE IF NOT EXISTS user_preferences
+(
+    argent_user UUID PRIMARY KEY
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    preferences JSONB NOT NULL DEFAULT '{}'
+);
+
+-- The version changes with every upload and is part of the avatar url,
+-- so clients can cache the image for as long as they like
+CREATE TABLE IF NOT EXISTS user_avatars
+(
+    argent_user  UUID PRIMARY KEY
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    content_type TEXT   NOT NULL,
+    image        BYTEA  NOT NULL,
+    version      BIGINT NOT NULL
+);
@@ -0,0 +1,34 @@
+-- Groups (households, friend circles) scope who users can find and share with
+CREATE TABLE IF NOT EXISTS groups
+(
+    id         UUID PRIMARY KEY,
+    name       TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS group_members
+(
+    group_id    UUID NOT NULL
+        REFERENCES groups
+        ON DELETE CASCADE,
+    argent_user UUID NOT NULL
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    role        TEXT NOT NULL,
+    PRIMARY KEY (group_id, argent_user)
+);
+
+CREATE INDEX IF NOT EXISTS group_members_argent_user ON group_members (argent_user);
+
+-- Everybody could share with everybody before groups, existing users keep
+-- that in one group which the admins own
+INSERT INTO groups (id, name)
+SELECT md5(random()::TEXT || clock_timestamp()::TEXT)::UUID, 'Everyone'
+WHERE EXISTS(SELECT 1 FROM argent_users);
+
+INSERT INTO group_members (group_id, argent_user, role)
+SELECT g.id,
+       u.id,
+       CASE WHEN u.role = 'Admin' THEN 'Owner' ELSE 'Member' END
+FROM groups g
+CROSS JOIN argent_users u;
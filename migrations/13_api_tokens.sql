@@ -0,0 +1,16 @@
+-- Personal access tokens for scripts, only the hash of a token is stored
+CREATE TABLE IF NOT EXISTS api_tokens
+(
+    id          UUID PRIMARY KEY,
+    argent_user UUID        NOT NULL
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    name        TEXT        NOT NULL,
+    token_hash  TEXT        NOT NULL UNIQUE,
+    scopes      TEXT[]      NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used   TIMESTAMPTZ,
+    expires_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_argent_user ON api_tokens (argent_user);
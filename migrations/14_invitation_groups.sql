@@ -0,0 +1,6 @@
+-- People who accept an invitation join this group, so that they have someone
+-- to share with from the start
+ALTER TABLE invitations
+    ADD COLUMN IF NOT EXISTS group_id UUID
+        REFERENCES groups
+        ON DELETE SET NULL;
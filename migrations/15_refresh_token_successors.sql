@@ -0,0 +1,3 @@
+-- The token a refresh token was rotated into, sealed with the refresh token
+-- itself, so a retried refresh can hand out the same successor again
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS successor TEXT;
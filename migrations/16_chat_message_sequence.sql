@@ -0,0 +1,18 @@
+-- Messages are numbered in the order they are committed, so that a stream
+-- can resume after the last message it saw without skipping any
+ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS seq BIGINT;
+CREATE SEQUENCE IF NOT EXISTS chat_messages_seq OWNED BY chat_messages.seq;
+
+UPDATE chat_messages
+SET seq = numbered.seq
+FROM (
+    SELECT id, row_number() OVER (ORDER BY created_date, id) AS seq
+    FROM chat_messages
+) numbered
+WHERE numbered.id = chat_messages.id;
+SELECT setval('chat_messages_seq', COALESCE(max(seq), 0) + 1, false) FROM chat_messages;
+
+ALTER TABLE chat_messages
+    ALTER COLUMN seq SET DEFAULT nextval('chat_messages_seq'),
+    ALTER COLUMN seq SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS chat_messages_seq_key ON chat_messages (seq);
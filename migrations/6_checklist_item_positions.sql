@@ -0,0 +1,16 @@
+ALTER TABLE checklistitems
+    ADD COLUMN IF NOT EXISTS position DOUBLE PRECISION;
+
+UPDATE checklistitems ci
+SET position = ordered.row_number * 1024
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY checklist ORDER BY created_at, id) AS row_number
+    FROM checklistitems
+) ordered
+WHERE ci.id = ordered.id;
+
+ALTER TABLE checklistitems
+    ALTER COLUMN position SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_checklistitems_checklist_position
+    ON checklistitems (checklist, position);
@@ -0,0 +1,16 @@
+-- Only hashes of refresh tokens are stored. Every login starts a family,
+-- refreshing marks the token used and adds its successor to the family.
+CREATE TABLE IF NOT EXISTS refresh_tokens
+(
+    token_hash  TEXT PRIMARY KEY,
+    family      UUID NOT NULL,
+    argent_user UUID NOT NULL
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at  TIMESTAMPTZ NOT NULL,
+    used_at     TIMESTAMPTZ,
+    revoked     BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family ON refresh_tokens (family);
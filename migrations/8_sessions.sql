@@ -0,0 +1,32 @@
+-- A session is one login on one device, its refresh tokens are the family
+-- that used to be tracked in refresh_tokens.family
+CREATE TABLE IF NOT EXISTS sessions
+(
+    id          UUID PRIMARY KEY,
+    argent_user UUID NOT NULL
+        REFERENCES argent_users
+        ON DELETE CASCADE,
+    user_agent  TEXT,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen   TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS sessions_argent_user ON sessions (argent_user);
+
+INSERT INTO sessions (id, argent_user, created_at, last_seen, revoked_at)
+SELECT family,
+       argent_user,
+       min(created_at),
+       max(created_at),
+       CASE WHEN bool_or(revoked) THEN now() END
+FROM refresh_tokens
+GROUP BY family, argent_user;
+
+ALTER TABLE refresh_tokens RENAME COLUMN family TO session;
+ALTER TABLE refresh_tokens
+    ADD CONSTRAINT refresh_tokens_session_fkey
+        FOREIGN KEY (session) REFERENCES sessions
+        ON DELETE CASCADE;
+ALTER TABLE refresh_tokens DROP COLUMN revoked;
+ALTER INDEX refresh_tokens_family RENAME TO refresh_tokens_session;
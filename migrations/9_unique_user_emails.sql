@@ -0,0 +1,21 @@
+-- Logins find users by email, so it has to identify a single user. Emails
+-- that only differ in case are the same address, like for invitations.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(format('%s (users %s)', address, users), '; ')
+    INTO duplicates
+    FROM (
+        SELECT lower(email) AS address, string_agg(id::TEXT, ', ' ORDER BY id) AS users
+        FROM argent_users
+        GROUP BY lower(email)
+        HAVING count(*) > 1
+    ) shared;
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Emails have to be unique, change or remove the users sharing one: %',
+            duplicates;
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS argent_users_email ON argent_users (lower(email));
@@ -1,11 +1,22 @@
 use rocket::http::{Cookie, SameSite};
-use rocket::time::{ext::NumericalDuration, OffsetDateTime};
+use rocket::time::{ext::NumericalDuration, Duration, OffsetDateTime};
+use uuid::Uuid;
 
-use crate::{config::AuthenticationConfig, data::users::models::User};
+use crate::{
+    config::AuthenticationConfig,
+    data::sessions::{models::RefreshToken, store::REFRESH_TOKEN_DAYS},
+};
 
 use super::jwt::generate_token;
 
-pub fn create_auth_cookie(config: &AuthenticationConfig, user: &User) -> Cookie<'static> {
+/// Access cookies closer than this to expiring are renewed by `SessionRenewal`
+pub const ACCESS_TOKEN_RENEW_WITHIN: Duration = Duration::minutes(10);
+
+pub fn create_auth_cookie(
+    config: &AuthenticationConfig,
+    user_id: Uuid,
+    session: Uuid,
+) -> Cookie<'static> {
     let duration = 30.minutes();
     let expiry_date = OffsetDateTime::now_utc().saturating_add(duration);
     // Since timestamps will always be positive this will not fail
@@ -13,7 +24,7 @@ pub fn create_auth_cookie(config: &AuthenticationConfig, user: &User) -> Cookie<
     let exp: usize = expiry_date.unix_timestamp().try_into().unwrap();
     Cookie::build(
         config.cookie_name.to_string(),
-        generate_token(&user, config, exp),
+        generate_token(user_id, session, config, exp),
     )
     .http_only(true)
     .secure(config.secure_cookie)
@@ -23,15 +34,33 @@ pub fn create_auth_cookie(config: &AuthenticationConfig, user: &User) -> Cookie<
     .finish()
 }
 
+pub fn create_refresh_cookie(
+    config: &AuthenticationConfig,
+    refresh_token: &RefreshToken,
+) -> Cookie<'static> {
+    let expiry_date = OffsetDateTime::now_utc().saturating_add((REFRESH_TOKEN_DAYS as i64).days());
+    Cookie::build(config.refresh_cookie_name(), refresh_token.token.clone())
+        .http_only(true)
+        .secure(config.secure_cookie)
+        .same_site(SameSite::Strict)
+        .path("/api/v1")
+        .expires(expiry_date)
+        .finish()
+}
+
 pub fn create_expired_cookie(config: &AuthenticationConfig) -> Cookie<'static> {
-    Cookie::build(config.cookie_name.to_string(), "")
+    expired_cookie(config, config.cookie_name.to_string())
+}
+
+pub fn create_expired_refresh_cookie(config: &AuthenticationConfig) -> Cookie<'static> {
+    expired_cookie(config, config.refresh_cookie_name())
+}
+
+fn expired_cookie(config: &AuthenticationConfig, name: String) -> Cookie<'static> {
+    Cookie::build(name, "")
         .http_only(true)
         .secure(config.secure_cookie)
-        .same_site(if config.secure_cookie {
-            SameSite::None
-        } else {
-            SameSite::Strict
-        })
+        .same_site(SameSite::Strict)
         .path("/api/v1")
         .expires(OffsetDateTime::UNIX_EPOCH)
         .finish()
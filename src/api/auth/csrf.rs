@@ -0,0 +1,98 @@
+use rocket::{
+    http::{Method, Status},
+    outcome::Outcome,
+    request::FromRequest,
+    Request,
+};
+
+use crate::{config::AuthenticationConfig, error::ArgentError};
+
+/// The scheme and host of a url, in lowercase
+fn origin_of(url: &str) -> Option<String> {
+    let host_start = url.find("://")? + 3;
+    let host_end = url[host_start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |end| host_start + end);
+    Some(url[..host_end].to_lowercase())
+}
+
+/// Where the request came from according to the browser, `Origin` or else `Referer`
+fn request_origin(request: &Request<'_>) -> Option<String> {
+    let headers = request.headers();
+    match headers.get_one("Origin") {
+        Some("null") => None,
+        Some(origin) => Some(origin.trim_end_matches('/').to_lowercase()),
+        None => headers.get_one("Referer").and_then(origin_of),
+    }
+}
+
+fn own_origin(request: &Request<'_>, auth_config: &AuthenticationConfig) -> Option<String> {
+    let scheme = if auth_config.secure_cookie {
+        "https"
+    } else {
+        "http"
+    };
+    let host = request.headers().get_one("Host")?;
+    Some(format!("{}://{}", scheme, host.to_lowercase()))
+}
+
+/// Browsers send cookies along with requests from other sites, so a
+/// state-changing request that authenticates with a cookie has to come from
+/// the API's own origin or one of the allowed origins
+pub fn check_origin(request: &Request<'_>) -> Result<(), ArgentError> {
+    if matches!(
+        request.method(),
+        Method::Get | Method::Head | Method::Options
+    ) {
+        return Ok(());
+    }
+    let auth_config = request.rocket().state::<AuthenticationConfig>().unwrap();
+    let origin = request_origin(request)
+        .ok_or_else(|| ArgentError::forbidden_msg("Missing Origin header"))?;
+    let allowed = own_origin(request, auth_config).as_ref() == Some(&origin)
+        || auth_config.allowed_origins.matches(&origin);
+    if allowed {
+        Ok(())
+    } else {
+        Err(ArgentError::forbidden_msg("Origin is not allowed"))
+    }
+}
+
+/// For routes that use a cookie without `AuthenticatedUser`, which checks the origin itself
+pub struct AllowedOrigin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AllowedOrigin {
+    type Error = ArgentError;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> Outcome<AllowedOrigin, (Status, Self::Error), ()> {
+        match check_origin(request) {
+            Ok(()) => Outcome::Success(AllowedOrigin),
+            Err(error) => Outcome::Failure((error.status_code(), error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::origin_of;
+
+    #[test]
+    fn origins_are_scheme_and_host() {
+        assert_eq!(
+            origin_of("https://Argent.example.com:8443/lists?id=1").as_deref(),
+            Some("https://argent.example.com:8443")
+        );
+        assert_eq!(
+            origin_of("http://localhost:8008").as_deref(),
+            Some("http://localhost:8008")
+        );
+        assert_eq!(
+            origin_of("https://argent.example.com#top").as_deref(),
+            Some("https://argent.example.com")
+        );
+        assert_eq!(origin_of("/relative/path"), None);
+    }
+}
@@ -8,7 +8,7 @@ use rocket::{
 
 use crate::error::ArgentError;
 
-use super::jwk::Jwks;
+use super::jwk::{Jwks, VerifiedIdentity};
 
 struct GoogleToken<'r>(&'r str);
 
@@ -36,7 +36,7 @@ impl<'r> FromRequest<'r> for GoogleToken<'r> {
     }
 }
 
-pub struct AuthenticatedGoogleMail(pub String);
+pub struct AuthenticatedGoogleMail(pub VerifiedIdentity);
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthenticatedGoogleMail {
@@ -52,7 +52,7 @@ impl<'r> FromRequest<'r> for AuthenticatedGoogleMail {
             .state::<Jwks>()
             .expect("Could not access JWKSStore");
         match jwks.validate_token(token.0).await {
-            Ok(verified_email) => Outcome::Success(Self(verified_email)),
+            Ok(identity) => Outcome::Success(Self(identity)),
             Err(error) => {
                 warn!("Could not verify token - {}", error);
                 Outcome::Failure((Status::Unauthorized, ArgentError::unauthorized()))
@@ -1,20 +1,37 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use jsonwebtoken::{DecodingKey, Validation};
-use rocket::{log::private::error, tokio::sync::RwLock};
+use reqwest::header::CACHE_CONTROL;
+use rocket::{
+    log::private::{error, warn},
+    tokio::{
+        select,
+        sync::{Mutex, RwLock},
+        time::{sleep_until, Instant},
+    },
+    Shutdown,
+};
 use serde::Deserialize;
 
-use crate::error::ArgentError;
-
-const CERT_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+use crate::{config::IdentityProviderConfig, error::ArgentError};
 
 #[derive(Deserialize)]
 struct GoogleToken {
     email: String,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+/// Who the identity provider says the token belongs to. `email_verified`
+/// is None for providers that do not say whether they checked the email.
+pub struct VerifiedIdentity {
+    pub email: String,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
 }
 
 #[derive(Clone, Deserialize)]
-struct Jwk {
+pub struct Jwk {
     pub kid: String,
     pub e: String,
     pub n: String,
@@ -25,77 +42,291 @@ struct JwkResponse {
     pub keys: Vec<Jwk>,
 }
 
+/// How often the keys are fetched when the provider sends no usable max-age
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Retry delay while the provider can't be reached
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Tokens with an unknown `kid` trigger a refresh at most this often
+const MISS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// A provider that does not answer in time counts as unreachable
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct RefreshState {
+    last_attempt: Option<Instant>,
+    next_refresh: Instant,
+}
+
+/// The identity provider's signing keys. A background task started with
+/// `refresh_periodically` keeps them current, when the provider can't be
+/// reached the last good keys stay in use.
+#[derive(Clone)]
 pub struct Jwks {
     jwk_map: Arc<RwLock<HashMap<String, Jwk>>>,
+    refresh_state: Arc<Mutex<RefreshState>>,
+    /// Held for the whole of a refresh, so others can wait for it to finish
+    refreshing: Arc<Mutex<()>>,
+    http: reqwest::Client,
+    config: Arc<IdentityProviderConfig>,
 }
 
 impl Jwks {
-    pub async fn new() -> Result<Self, ArgentError> {
-        let new = Self {
-            jwk_map: Arc::new(RwLock::new(HashMap::new())),
-        };
-        new.refresh_jwks().await?;
-        Ok(new)
+    /// Fetches the initial keys. If that fails the verifier starts without
+    /// keys and rejects every login until a later refresh succeeds.
+    pub async fn new(config: IdentityProviderConfig) -> Self {
+        let new = Self::from_keys(config, Vec::new());
+        if new.refresh_jwks().await.is_err() {
+            warn!("Starting without identity provider keys, logins fail until they can be fetched");
+        }
+        new
+    }
+
+    /// A verifier starting out with the given keys instead of fetching them
+    pub fn from_keys(config: IdentityProviderConfig, keys: Vec<Jwk>) -> Self {
+        let jwk_map = keys
+            .into_iter()
+            .map(|key| (key.kid.clone(), key))
+            .collect::<HashMap<_, _>>();
+        Self {
+            jwk_map: Arc::new(RwLock::new(jwk_map)),
+            refresh_state: Arc::new(Mutex::new(RefreshState {
+                last_attempt: None,
+                next_refresh: Instant::now() + DEFAULT_REFRESH_INTERVAL,
+            })),
+            refreshing: Arc::new(Mutex::new(())),
+            http: reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .expect("Could not create the HTTP client"),
+            config: Arc::new(config),
+        }
     }
 
-    fn validate_with_jwk(jwk: Jwk, token: &str) -> Result<String, ArgentError> {
-        let val = Validation::new(jsonwebtoken::Algorithm::RS256);
+    fn validate_with_jwk(&self, jwk: Jwk, token: &str) -> Result<VerifiedIdentity, ArgentError> {
+        let mut val = Validation::new(jsonwebtoken::Algorithm::RS256);
+        val.set_issuer(&self.config.issuers);
+        val.set_audience(&[&self.config.audience]);
+        // Otherwise tokens without them would pass the issuer and audience checks
+        val.set_required_spec_claims(&["exp", "iss", "aud"]);
         let decoded = jsonwebtoken::decode::<GoogleToken>(
             token,
             &DecodingKey::from_rsa_components(&jwk.n, &jwk.e)?,
             &val,
-        )
-        .unwrap();
-        Ok(decoded.claims.email)
+        )?;
+        Ok(VerifiedIdentity {
+            email: decoded.claims.email,
+            email_verified: decoded.claims.email_verified,
+            name: decoded.claims.name,
+        })
     }
 
-    async fn get_current_keys() -> Result<HashMap<String, Jwk>, ArgentError> {
-        Ok(reqwest::get(CERT_URL)
+    /// The current keys and how long they may be cached
+    async fn get_current_keys(&self) -> Result<(HashMap<String, Jwk>, Duration), ArgentError> {
+        let response = self
+            .http
+            .get(&self.config.jwks_url)
+            .send()
             .await?
+            .error_for_status()?;
+        let cache_for = response
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(max_age)
+            .unwrap_or(DEFAULT_REFRESH_INTERVAL)
+            .clamp(MIN_REFRESH_INTERVAL, MAX_REFRESH_INTERVAL);
+        let keys = response
             .json::<JwkResponse>()
             .await?
             .keys
-            .iter()
-            .map(|key| (key.kid.clone(), key.to_owned()))
-            .collect::<HashMap<_, _>>())
+            .into_iter()
+            .map(|key| (key.kid.clone(), key))
+            .collect::<HashMap<_, _>>();
+        Ok((keys, cache_for))
     }
 
+    /// Replaces the keys with the provider's current ones. The map is only
+    /// locked to swap in the new keys, never while waiting on the provider.
     async fn refresh_jwks(&self) -> Result<(), ArgentError> {
-        let mut jwk_map = self.jwk_map.write().await;
-        let current_keys = &Self::get_current_keys().await;
-        match current_keys {
-            Ok(current_keys) => {
-                jwk_map.clone_from(current_keys);
+        let _refreshing = self.refreshing.lock().await;
+        self.refresh_state.lock().await.last_attempt = Some(Instant::now());
+        self.fetch_and_store().await
+    }
+
+    async fn fetch_and_store(&self) -> Result<(), ArgentError> {
+        let result = self.get_current_keys().await;
+        let mut refresh_state = self.refresh_state.lock().await;
+        match result {
+            Ok((current_keys, cache_for)) => {
+                *self.jwk_map.write().await = current_keys;
+                refresh_state.next_refresh = Instant::now() + cache_for;
                 Ok(())
             }
             Err(err) => {
-                drop(jwk_map);
-                error!("{}", err);
+                error!("Could not refresh identity provider keys - {}", err);
+                refresh_state.next_refresh = Instant::now() + RETRY_INTERVAL;
                 Err(ArgentError::server_error())
             }
         }
     }
 
-    pub async fn validate_token(&self, token: &str) -> Result<String, ArgentError> {
+    /// Refreshes for an unknown `kid`, unless that was already tried recently.
+    /// Concurrent misses wait for the same refresh instead of starting their own.
+    async fn refresh_on_miss(&self) {
+        // Waits for a refresh in progress, which then counts as recent
+        let _refreshing = self.refreshing.lock().await;
+        let mut refresh_state = self.refresh_state.lock().await;
+        let refreshed_recently = refresh_state
+            .last_attempt
+            .is_some_and(|at| at.elapsed() < MISS_REFRESH_INTERVAL);
+        if refreshed_recently {
+            return;
+        }
+        refresh_state.last_attempt = Some(Instant::now());
+        drop(refresh_state);
+        self.fetch_and_store().await.ok();
+    }
+
+    /// Refreshes the keys whenever they expire until the server shuts down
+    pub async fn refresh_periodically(self, mut shutdown: Shutdown) {
+        loop {
+            let next_refresh = self.refresh_state.lock().await.next_refresh;
+            select! {
+                _ = sleep_until(next_refresh) => {}
+                _ = &mut shutdown => break,
+            }
+            self.refresh_jwks().await.ok();
+        }
+    }
+
+    pub async fn validate_token(&self, token: &str) -> Result<VerifiedIdentity, ArgentError> {
         // get key id
         let header = jsonwebtoken::decode_header(token)?;
-        let kid = header.kid.ok_or_else(|| ArgentError::unauthorized())?;
-        // Try using cache
-        let jwk = match self.jwk_map.read().await.get(&kid).cloned() {
+        let kid = header.kid.ok_or_else(ArgentError::unauthorized)?;
+        // Try using cache, the read lock must be released before a refresh
+        let cached = self.jwk_map.read().await.get(&kid).cloned();
+        let jwk = match cached {
             Some(jwk) => jwk,
             None => {
-                self.refresh_jwks().await?;
+                self.refresh_on_miss().await;
                 self.jwk_map
                     .read()
                     .await
-                    .get(token)
+                    .get(&kid)
                     .cloned()
-                    .ok_or_else(|| ArgentError::unauthorized())?
+                    .ok_or_else(ArgentError::unauthorized)?
             }
         };
-        Self::validate_with_jwk(jwk, token)
+        self.validate_with_jwk(jwk, token)
     }
 }
 
+/// The `max-age` directive of a Cache-Control header
+fn max_age(cache_control: &str) -> Option<Duration> {
+    cache_control
+        .split(',')
+        .filter_map(|directive| directive.trim().split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case("max-age"))
+        .and_then(|(_, seconds)| seconds.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
 unsafe impl Send for Jwks {}
 unsafe impl Sync for Jwks {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{google_token, id_token_with_kid, test_idp_config, test_jwk, LocalJwks};
+
+    fn unreachable_idp_config() -> IdentityProviderConfig {
+        IdentityProviderConfig {
+            jwks_url: String::from("http://127.0.0.1:1/jwks"),
+            ..test_idp_config()
+        }
+    }
+
+    #[test]
+    fn max_age_reads_the_cache_control_directive() {
+        assert_eq!(
+            max_age("public, max-age=19204, must-revalidate, no-transform"),
+            Some(Duration::from_secs(19204))
+        );
+        assert_eq!(max_age("no-cache"), None);
+        assert_eq!(max_age("max-age=soon"), None);
+    }
+
+    #[rocket::async_test]
+    async fn fetches_keys_and_caches_them_for_max_age() {
+        let provider = LocalJwks::serve("public, max-age=600").await;
+        let jwks = Jwks::new(provider.idp_config()).await;
+
+        assert!(jwks
+            .validate_token(&google_token("user@test.argent"))
+            .await
+            .is_ok());
+        assert_eq!(provider.requests(), 1);
+        let next_refresh = jwks.refresh_state.lock().await.next_refresh;
+        assert!(next_refresh > Instant::now() + Duration::from_secs(590));
+    }
+
+    #[rocket::async_test]
+    async fn starts_without_keys_when_the_provider_is_down() {
+        let jwks = Jwks::new(unreachable_idp_config()).await;
+
+        assert!(jwks
+            .validate_token(&google_token("user@test.argent"))
+            .await
+            .is_err());
+    }
+
+    #[rocket::async_test]
+    async fn keeps_the_last_good_keys_when_the_provider_is_down() {
+        let jwks = Jwks::from_keys(unreachable_idp_config(), vec![test_jwk()]);
+
+        assert!(jwks.refresh_jwks().await.is_err());
+        assert!(jwks
+            .validate_token(&google_token("user@test.argent"))
+            .await
+            .is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn unknown_kids_refresh_at_most_once_per_interval() {
+        let provider = LocalJwks::serve("max-age=600").await;
+        let jwks = Jwks::new(provider.idp_config()).await;
+
+        for kid in ["bogus-1", "bogus-2", "bogus-3"] {
+            let token = id_token_with_kid("user@test.argent", kid);
+            assert!(jwks.validate_token(&token).await.is_err());
+        }
+        assert_eq!(provider.requests(), 1);
+
+        jwks.refresh_state.lock().await.last_attempt = None;
+        let token = id_token_with_kid("user@test.argent", "bogus-4");
+        assert!(jwks.validate_token(&token).await.is_err());
+        assert_eq!(provider.requests(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_misses_wait_for_the_same_refresh() {
+        let provider = LocalJwks::serve("max-age=600").await;
+        let jwks = Jwks::from_keys(provider.idp_config(), Vec::new());
+
+        let validations = (0..2)
+            .map(|_| {
+                let jwks = jwks.clone();
+                rocket::tokio::spawn(async move {
+                    jwks.validate_token(&google_token("user@test.argent"))
+                        .await
+                        .is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        for validation in validations {
+            assert!(validation.await.unwrap());
+        }
+        assert_eq!(provider.requests(), 1);
+    }
+}
@@ -1,37 +1,59 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Header, Validation};
+use rocket::time::OffsetDateTime;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::{config::AuthenticationConfig, data::users::models::User, error::ArgentError};
+use crate::{config::AuthenticationConfig, error::ArgentError};
 
+/// Only who the token is for and which session it belongs to, the user
+/// itself is looked up on every request so changes apply right away.
 #[derive(Serialize, Deserialize)]
-struct Claims {
-    exp: usize,
-    user: User,
+pub struct Claims {
+    pub exp: usize,
+    pub iat: usize,
+    #[serde(rename = "sub")]
+    pub user_id: Uuid,
+    pub session: Uuid,
 }
 
 impl Claims {
-    fn new(user: User, exp: usize) -> Self {
-        Self { exp, user }
+    fn new(user_id: Uuid, session: Uuid, exp: usize) -> Self {
+        let iat = OffsetDateTime::now_utc().unix_timestamp() as usize;
+        Self {
+            exp,
+            iat,
+            user_id,
+            session,
+        }
     }
 }
 
-pub fn generate_token(user: &User, auth_config: &AuthenticationConfig, exp: usize) -> String {
-    let claims = Claims::new(user.clone(), exp);
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(auth_config.jwt_key.as_bytes()),
-    )
-    .expect("Failed making token")
+/// Signed with the active key of the keyring, whose kid goes in the header
+pub fn generate_token(
+    user_id: Uuid,
+    session: Uuid,
+    auth_config: &AuthenticationConfig,
+    exp: usize,
+) -> String {
+    let claims = Claims::new(user_id, session, exp);
+    let (kid, algorithm, key) = auth_config.keyring.signing_key();
+    let header = Header {
+        kid: Some(kid.to_string()),
+        ..Header::new(algorithm)
+    };
+    encode(&header, &claims, key).expect("Failed making token")
 }
 
-pub fn decode_token(token: &str, auth_config: &AuthenticationConfig) -> Result<User, ArgentError> {
-    let user = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(&auth_config.jwt_key.as_bytes()),
-        &Validation::default(),
-    )?
-    .claims
-    .user;
-    Ok(user)
+/// Verified with the key named by the kid in the header, which may be a retired one
+pub fn decode_token(
+    token: &str,
+    auth_config: &AuthenticationConfig,
+) -> Result<Claims, ArgentError> {
+    let header = decode_header(token)?;
+    let (algorithm, key) = auth_config
+        .keyring
+        .verification_key(header.kid.as_deref())
+        .ok_or_else(|| ArgentError::unauthorized_msg("Unknown signing key"))?;
+    let claims = decode::<Claims>(token, key, &Validation::new(*algorithm))?.claims;
+    Ok(claims)
 }
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, fmt, fs, sync::Arc};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+
+use crate::config::SigningKeyConfig;
+
+/// The kid of the single `jwt_key` from before keyrings, also used to
+/// verify tokens that have no kid in their header
+pub const DEFAULT_KID: &str = "default";
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: EncodingKey,
+}
+
+/// One active key signs new session tokens, every key including the
+/// retired ones verifies them. Keys are found by the `kid` of a token.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    signing: Option<Arc<SigningKey>>,
+    verification: Arc<HashMap<String, (Algorithm, DecodingKey)>>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("signing", &self.signing.as_ref().map(|key| &key.kid))
+            .field("verification", &self.verification.keys())
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// `jwt_key` is the active key when there are no other keys and retired otherwise
+    pub fn load(jwt_key: Option<&str>, keys: &[SigningKeyConfig]) -> Result<Keyring, String> {
+        let mut signing = None;
+        let mut verification = HashMap::new();
+        if let Some(jwt_key) = jwt_key {
+            verification.insert(
+                DEFAULT_KID.to_string(),
+                (
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(jwt_key.as_bytes()),
+                ),
+            );
+            if keys.is_empty() {
+                signing = Some(SigningKey {
+                    kid: DEFAULT_KID.to_string(),
+                    algorithm: Algorithm::HS256,
+                    key: EncodingKey::from_secret(jwt_key.as_bytes()),
+                });
+            }
+        }
+        for config in keys {
+            let key =
+                load_decoding_key(config).map_err(|e| format!("key {}: {}", config.kid, e))?;
+            if verification.insert(config.kid.clone(), key).is_some() {
+                return Err(format!("key {} is configured more than once", config.kid));
+            }
+            if config.retired {
+                continue;
+            }
+            if signing.is_some() {
+                return Err(String::from(
+                    "only one key can be active, retire the others",
+                ));
+            }
+            signing = Some(SigningKey {
+                kid: config.kid.clone(),
+                algorithm: config.algorithm,
+                key: load_encoding_key(config).map_err(|e| format!("key {}: {}", config.kid, e))?,
+            });
+        }
+        let signing =
+            signing.ok_or_else(|| String::from("no active key, set jwt_key or add a key"))?;
+        Ok(Keyring {
+            signing: Some(Arc::new(signing)),
+            verification: Arc::new(verification),
+        })
+    }
+
+    /// The kid, algorithm and key new tokens are signed with
+    pub fn signing_key(&self) -> (&str, Algorithm, &EncodingKey) {
+        let signing = self.signing.as_ref().expect("Keyring has not been loaded");
+        (&signing.kid, signing.algorithm, &signing.key)
+    }
+
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&(Algorithm, DecodingKey)> {
+        self.verification.get(kid.unwrap_or(DEFAULT_KID))
+    }
+}
+
+fn read_pem(file: &Option<String>, name: &str) -> Result<Vec<u8>, String> {
+    let file = file
+        .as_ref()
+        .ok_or_else(|| format!("{} is required for this algorithm", name))?;
+    fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))
+}
+
+fn secret(config: &SigningKeyConfig) -> Result<&[u8], String> {
+    config
+        .secret
+        .as_ref()
+        .map(|secret| secret.as_bytes())
+        .ok_or_else(|| String::from("secret is required for HS256"))
+}
+
+fn load_encoding_key(config: &SigningKeyConfig) -> Result<EncodingKey, String> {
+    let key = match config.algorithm {
+        Algorithm::HS256 => return Ok(EncodingKey::from_secret(secret(config)?)),
+        Algorithm::ES256 => {
+            EncodingKey::from_ec_pem(&read_pem(&config.private_key_file, "private_key_file")?)
+        }
+        Algorithm::EdDSA => {
+            EncodingKey::from_ed_pem(&read_pem(&config.private_key_file, "private_key_file")?)
+        }
+        other => return Err(format!("{:?} is not supported", other)),
+    };
+    key.map_err(|e| format!("invalid private key: {}", e))
+}
+
+fn load_decoding_key(config: &SigningKeyConfig) -> Result<(Algorithm, DecodingKey), String> {
+    let key = match config.algorithm {
+        Algorithm::HS256 => Ok(DecodingKey::from_secret(secret(config)?)),
+        Algorithm::ES256 => {
+            DecodingKey::from_ec_pem(&read_pem(&config.public_key_file, "public_key_file")?)
+        }
+        Algorithm::EdDSA => {
+            DecodingKey::from_ed_pem(&read_pem(&config.public_key_file, "public_key_file")?)
+        }
+        other => return Err(format!("{:?} is not supported", other)),
+    };
+    key.map(|key| (config.algorithm, key))
+        .map_err(|e| format!("invalid public key: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Algorithm;
+    use uuid::Uuid;
+
+    use crate::{
+        api::auth::jwt::{decode_token, generate_token},
+        config::{AuthenticationConfig, SigningKeyConfig},
+        testing::test_auth_config,
+    };
+
+    const EXP: usize = 4_000_000_000;
+
+    fn hs256(kid: &str, retired: bool) -> SigningKeyConfig {
+        SigningKeyConfig {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            secret: Some(format!("{}-secret", kid)),
+            private_key_file: None,
+            public_key_file: None,
+            retired,
+        }
+    }
+
+    fn pem_key(kid: &str, algorithm: Algorithm, name: &str) -> SigningKeyConfig {
+        let fixture = |kind| {
+            Some(format!(
+                "{}/tests/fixtures/session_{}_{}.pem",
+                env!("CARGO_MANIFEST_DIR"),
+                name,
+                kind
+            ))
+        };
+        SigningKeyConfig {
+            kid: kid.to_string(),
+            algorithm,
+            secret: None,
+            private_key_file: fixture("private"),
+            public_key_file: fixture("public"),
+            retired: false,
+        }
+    }
+
+    fn config(jwt_key: Option<&str>, signing_keys: Vec<SigningKeyConfig>) -> AuthenticationConfig {
+        AuthenticationConfig {
+            jwt_key: jwt_key.map(String::from),
+            signing_keys,
+            ..test_auth_config()
+        }
+        .load_keys()
+        .unwrap()
+    }
+
+    #[test]
+    fn tokens_stay_valid_after_their_key_is_retired() {
+        let user = Uuid::new_v4();
+        let session = Uuid::new_v4();
+        let before = config(None, vec![hs256("one", false)]);
+        let token = generate_token(user, session, &before, EXP);
+
+        let after = config(None, vec![hs256("one", true), hs256("two", false)]);
+        assert_eq!(decode_token(&token, &after).unwrap().user_id, user);
+        let new_token = generate_token(user, session, &after, EXP);
+        assert!(decode_token(&new_token, &before).is_err());
+
+        let dropped = config(None, vec![hs256("two", false)]);
+        assert!(decode_token(&token, &dropped).is_err());
+    }
+
+    #[test]
+    fn tokens_without_kid_use_the_jwt_key() {
+        let user = Uuid::new_v4();
+        let legacy = config(Some("legacy"), Vec::new());
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &legacy_claims(user),
+            &jsonwebtoken::EncodingKey::from_secret(b"legacy"),
+        )
+        .unwrap();
+        assert_eq!(decode_token(&token, &legacy).unwrap().user_id, user);
+
+        let rotated = config(Some("legacy"), vec![hs256("one", false)]);
+        assert_eq!(decode_token(&token, &rotated).unwrap().user_id, user);
+    }
+
+    fn legacy_claims(user: Uuid) -> rocket::serde::json::Value {
+        rocket::serde::json::json!({
+            "exp": EXP,
+            "iat": 0,
+            "sub": user,
+            "session": Uuid::new_v4(),
+        })
+    }
+
+    #[test]
+    fn asymmetric_keys_sign_and_verify() {
+        for (algorithm, name) in [(Algorithm::ES256, "es256"), (Algorithm::EdDSA, "ed25519")] {
+            let user = Uuid::new_v4();
+            let keys = config(None, vec![pem_key(name, algorithm, name)]);
+            let token = generate_token(user, Uuid::new_v4(), &keys, EXP);
+            assert_eq!(decode_token(&token, &keys).unwrap().user_id, user);
+        }
+    }
+
+    #[test]
+    fn keyrings_need_exactly_one_active_key() {
+        let load = |keys: Vec<SigningKeyConfig>| {
+            AuthenticationConfig {
+                jwt_key: None,
+                signing_keys: keys,
+                ..test_auth_config()
+            }
+            .load_keys()
+        };
+        assert!(load(Vec::new()).is_err());
+        assert!(load(vec![hs256("one", true)]).is_err());
+        assert!(load(vec![hs256("one", false), hs256("two", false)]).is_err());
+        assert!(load(vec![hs256("one", false), hs256("one", true)]).is_err());
+        let mut missing_file = pem_key("es", Algorithm::ES256, "es256");
+        missing_file.private_key_file = None;
+        assert!(load(vec![missing_file]).is_err());
+    }
+}
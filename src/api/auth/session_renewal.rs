@@ -0,0 +1,61 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Cookie,
+    time::OffsetDateTime,
+    Data, Request,
+};
+use rocket_db_pools::Database;
+
+use crate::{
+    config::AuthenticationConfig,
+    data::{secrets::hash_token, sessions::store::SessionStore, ArgentDB},
+};
+
+use super::{
+    cookie::{create_auth_cookie, ACCESS_TOKEN_RENEW_WITHIN},
+    jwt::decode_token,
+};
+
+/// Renews the access cookie when it is about to expire, as long as the
+/// refresh token sent along with it is still active. Rotating the refresh
+/// token is left to `/auth/refresh`, when done here concurrent requests
+/// would look like a reused token.
+pub struct SessionRenewal;
+
+async fn renewed_auth_cookie(request: &Request<'_>) -> Option<Cookie<'static>> {
+    let auth_config = request.rocket().state::<AuthenticationConfig>()?;
+    let cookies = request.cookies();
+    let access_token = cookies.get(&auth_config.cookie_name)?;
+    let claims = decode_token(access_token.value(), auth_config).ok()?;
+    let renew_from = (OffsetDateTime::now_utc() + ACCESS_TOKEN_RENEW_WITHIN).unix_timestamp();
+    if claims.exp as i64 > renew_from {
+        return None;
+    }
+
+    let refresh_token = cookies.get(&auth_config.refresh_cookie_name())?;
+    let mut conn = ArgentDB::fetch(request.rocket())?.acquire().await.ok()?;
+    let active = SessionStore::refresh_token_is_active_conn(
+        &mut conn,
+        &hash_token(refresh_token.value()),
+        claims.session,
+    )
+    .await
+    .ok()?;
+    active.then(|| create_auth_cookie(auth_config, claims.user_id, claims.session))
+}
+
+#[rocket::async_trait]
+impl Fairing for SessionRenewal {
+    fn info(&self) -> Info {
+        Info {
+            name: "Renew access cookies close to expiring",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        if let Some(auth_cookie) = renewed_auth_cookie(request).await {
+            request.cookies().add(auth_cookie);
+        }
+    }
+}
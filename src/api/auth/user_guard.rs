@@ -1,30 +1,125 @@
 use rocket::{
     http::{CookieJar, Status},
-    outcome::Outcome,
+    outcome::{try_outcome, Outcome},
     request::FromRequest,
     Request,
 };
+use rocket_db_pools::Connection;
+use sqlx::{pool::PoolConnection, Postgres};
+use uuid::Uuid;
 
-use crate::{config::AuthenticationConfig, data::users::models::User, error::ArgentError};
+use crate::{
+    config::AuthenticationConfig,
+    data::{
+        api_tokens::{models::Scope, store::ApiTokenStore},
+        sessions::store::SessionStore,
+        users::{
+            cache::UserCache,
+            models::{User, UserRole},
+            store::UsersStore,
+        },
+        ArgentDB,
+    },
+    error::ArgentError,
+};
 
-use super::jwt::decode_token;
+use super::{
+    csrf::check_origin,
+    jwt::{decode_token, Claims},
+};
 
-fn get_user_from_cookie(
+fn get_claims_from_cookie(
     cookies: &CookieJar,
     auth_config: &AuthenticationConfig,
-) -> Result<User, ArgentError> {
+) -> Result<Claims, ArgentError> {
     let token = cookies
         .get(&auth_config.cookie_name)
         .map(|cookie| cookie.value())
-        .ok_or(ArgentError::unauthorized_msg("No authentication cookie"))?;
+        .ok_or_else(|| ArgentError::unauthorized_msg("No authentication cookie"))?;
     decode_token(token, auth_config)
 }
 
+/// The token of an `Authorization: Bearer` header, if there is such a header
+fn get_bearer_token<'r>(request: &'r Request<'_>) -> Result<Option<&'r str>, ArgentError> {
+    match request.headers().get_one("Authorization") {
+        Some(header) => header
+            .strip_prefix("Bearer ")
+            .map(|token| Some(token.trim()))
+            .ok_or_else(|| ArgentError::unauthorized_msg("Only bearer tokens are supported")),
+        None => Ok(None),
+    }
+}
+
+/// The owner of a valid API token that has the scope this request needs
+async fn authenticate_token(
+    request: &Request<'_>,
+    db: &mut PoolConnection<Postgres>,
+    token: &str,
+) -> Result<Uuid, ArgentError> {
+    let grant = ApiTokenStore::authenticate_conn(db, token)
+        .await?
+        .ok_or_else(|| ArgentError::unauthorized_msg("Invalid API token"))?;
+    let scope = Scope::required_for(request.method(), request.uri().path().as_str())
+        .ok_or_else(|| ArgentError::forbidden_msg("API tokens cannot be used for this request"))?;
+    if grant.scopes.contains(&scope) {
+        Ok(grant.argent_user)
+    } else {
+        Err(ArgentError::forbidden_msg(&format!(
+            "API token lacks the {} scope",
+            scope.as_str()
+        )))
+    }
+}
+
+/// The current state of the user of a valid access cookie whose session
+/// has not been revoked, or of a valid API token. A bearer token takes
+/// precedence over the cookie and, not being sent by browsers on their own,
+/// needs no origin check.
+async fn authenticate(request: &Request<'_>) -> Result<AuthenticatedUser, ArgentError> {
+    let mut db = request
+        .guard::<Connection<ArgentDB>>()
+        .await
+        .success_or_else(ArgentError::server_error)?;
+    let (user_id, session) = match get_bearer_token(request)? {
+        Some(token) => (authenticate_token(request, &mut db, token).await?, None),
+        None => {
+            check_origin(request)?;
+            let auth_config = request.rocket().state::<AuthenticationConfig>().unwrap();
+            let claims = get_claims_from_cookie(request.cookies(), auth_config)?;
+            if !SessionStore::touch_session_conn(&mut db, claims.session, claims.user_id).await? {
+                return Err(ArgentError::unauthorized_msg("Session has been revoked"));
+            }
+            (claims.user_id, Some(claims.session))
+        }
+    };
+    let cache = request.rocket().state::<UserCache>().unwrap();
+    let user = match cache.get(user_id) {
+        Some(user) => user,
+        None => {
+            let user = UsersStore::find_user_conn(&mut db, user_id)
+                .await?
+                .ok_or_else(|| ArgentError::unauthorized_msg("User no longer exists"))?;
+            cache.insert(user.clone());
+            user
+        }
+    };
+    Ok(AuthenticatedUser { user, session })
+}
+
 #[derive(Debug)]
-pub struct AuthenticatedUser(User);
+pub struct AuthenticatedUser {
+    user: User,
+    session: Option<Uuid>,
+}
+
 impl AuthenticatedUser {
     pub fn get(self) -> User {
-        self.0
+        self.user
+    }
+
+    /// None when authenticated with an API token
+    pub fn session(&self) -> Option<Uuid> {
+        self.session
     }
 }
 
@@ -35,13 +130,140 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
     async fn from_request(
         request: &'r Request<'_>,
     ) -> Outcome<AuthenticatedUser, (Status, Self::Error), ()> {
-        let cookies = request.guard::<&CookieJar>().await.unwrap();
-        let auth_config = request.rocket().state::<AuthenticationConfig>().unwrap();
-        let user = get_user_from_cookie(cookies, &auth_config);
-
-        match user {
-            Ok(user) => Outcome::Success(AuthenticatedUser(user)),
+        match authenticate(request).await {
+            Ok(user) => Outcome::Success(user),
             Err(error) => Outcome::Failure((error.status_code(), error)),
         }
     }
 }
+
+/// An authenticated user with the admin role, anyone else is forbidden
+#[derive(Debug)]
+pub struct AdminUser(User);
+
+impl AdminUser {
+    pub fn get(self) -> User {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ArgentError;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> Outcome<AdminUser, (Status, Self::Error), ()> {
+        let user = try_outcome!(request.guard::<AuthenticatedUser>().await).get();
+        if user.role == UserRole::Admin {
+            Outcome::Success(AdminUser(user))
+        } else {
+            Outcome::Failure((Status::Forbidden, ArgentError::forbidden()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{Header, Status},
+        serde::json::json,
+    };
+
+    use crate::{
+        data::users::cache::UserCache,
+        testing::{add_user, auth_cookie, connection, get_as, TestApp},
+    };
+
+    #[rocket::async_test]
+    async fn resolves_the_current_user_instead_of_trusting_the_token() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "before").await;
+        let (_, me) = get_as(client, &user, "/api/v1/me").await;
+        assert_eq!(me["name"], "before");
+
+        sqlx::query("UPDATE argent_users SET name = 'after', role = 'Admin' WHERE id = $1")
+            .bind(user.id)
+            .execute(&mut connection(client).await)
+            .await
+            .unwrap();
+        client
+            .rocket()
+            .state::<UserCache>()
+            .unwrap()
+            .invalidate(user.id);
+
+        let (status, me) = get_as(client, &user, "/api/v1/me").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["name"], "after");
+        assert_eq!(me["role"], "Admin");
+    }
+
+    #[rocket::async_test]
+    async fn deleted_users_are_rejected() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let cookie = auth_cookie(client, &user).await;
+
+        sqlx::query("DELETE FROM argent_users WHERE id = $1")
+            .bind(user.id)
+            .execute(&mut connection(client).await)
+            .await
+            .unwrap();
+
+        let response = client.get("/api/v1/me").cookie(cookie).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn cookie_requests_that_change_state_need_an_allowed_origin() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let checklist = json!({ "name": "groceries" });
+        let cases = [
+            (None, None, Status::Forbidden),
+            (
+                Some(("Origin", "https://evil.example")),
+                None,
+                Status::Forbidden,
+            ),
+            (Some(("Origin", "null")), None, Status::Forbidden),
+            (Some(("Origin", "http://argent.test")), None, Status::Ok),
+            (
+                Some(("Referer", "http://argent.test/lists")),
+                None,
+                Status::Ok,
+            ),
+            (
+                Some(("Referer", "https://evil.example/argent.test")),
+                None,
+                Status::Forbidden,
+            ),
+            (
+                Some(("Origin", "http://api.argent.example")),
+                Some("api.argent.example"),
+                Status::Ok,
+            ),
+        ];
+        for (header, host, expected) in cases {
+            let mut request = client
+                .post("/api/v1/checklists")
+                .cookie(auth_cookie(client, &user).await)
+                .json(&checklist);
+            if let Some((name, value)) = header {
+                request = request.header(Header::new(name, value));
+            }
+            if let Some(host) = host {
+                request = request.header(Header::new("Host", host));
+            }
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), expected, "{:?} {:?}", header, host);
+        }
+
+        let (status, _) = get_as(client, &user, "/api/v1/checklists").await;
+        assert_eq!(status, Status::Ok);
+    }
+}
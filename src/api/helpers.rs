@@ -1,4 +1,12 @@
-use rocket::{http::Status, response::Responder, serde::json::Json};
+use std::convert::Infallible;
+
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    response::Responder,
+    serde::json::Json,
+    Request,
+};
 use serde::Serialize;
 use uuid::Uuid;
 
@@ -60,3 +68,42 @@ pub fn convert_uuid(rocket_uuid: &rocket::serde::uuid::Uuid) -> Uuid {
     let bytes: &[u8; 16] = rocket_uuid.as_bytes();
     Uuid::from_bytes(*bytes)
 }
+
+/// The `User-Agent` header, recorded to tell sessions apart
+pub struct UserAgent(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAgent {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let user_agent = request.headers().get_one("User-Agent").map(String::from);
+        Outcome::Success(UserAgent(user_agent))
+    }
+}
+
+/// The `Last-Event-ID` header sent by an `EventSource` when it reconnects
+pub struct LastEventId(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let last_event_id = request.headers().get_one("Last-Event-ID").map(String::from);
+        Outcome::Success(LastEventId(last_event_id))
+    }
+}
+
+/// The `If-None-Match` header, to answer conditional requests with 304 Not Modified
+pub struct IfNoneMatch(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let if_none_match = request.headers().get_one("If-None-Match").map(String::from);
+        Outcome::Success(IfNoneMatch(if_none_match))
+    }
+}
@@ -3,10 +3,17 @@ use rocket::Route;
 use crate::cors::CORS;
 
 use self::checklists_controller::checklist_routes;
+mod admin_controller;
 mod auth_controller;
+mod chat_controller;
 mod checklists_controller;
+mod groups_controller;
+mod invitations_controller;
 mod marble_game_controller;
+mod sessions_controller;
+mod tokens_controller;
 mod users_controller;
+mod wishlists_controller;
 
 pub struct ApiV1Routes {}
 impl ApiV1Routes {
@@ -14,8 +21,15 @@ impl ApiV1Routes {
         let routes = [
             checklist_routes(),
             auth_controller::routes(),
+            admin_controller::routes(),
+            groups_controller::routes(),
+            invitations_controller::routes(),
+            sessions_controller::routes(),
+            tokens_controller::routes(),
             users_controller::routes(),
             marble_game_controller::routes(),
+            wishlists_controller::routes(),
+            chat_controller::routes(),
         ]
         .concat();
         CORS::add_options_method(routes)
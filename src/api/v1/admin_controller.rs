@@ -0,0 +1,319 @@
+use rocket::{
+    delete, get, http::Status, patch, post, routes, serde, serde::json::Json, Route, State,
+};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        auth::user_guard::AdminUser,
+        helpers::{convert_uuid, parse_uuid, ArgentApiResult, ArgentResult, NewData, OkData},
+    },
+    data::{
+        checklists::events::{ChecklistBroadcast, ChecklistChange, ChecklistEvent},
+        groups::{
+            models::{Group, GroupRequest, GroupUpdateRequest},
+            store::GroupStore,
+        },
+        users::{
+            models::{NewUserRequest, User, UserUpdateRequest},
+            store::UsersStore,
+        },
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+#[get("/admin/users")]
+async fn get_users(mut users_store: UsersStore, _admin: AdminUser) -> ArgentApiResult<Vec<User>> {
+    let users = users_store.get_all_users().await?;
+    ArgentApiResult::new(users)
+}
+
+#[post("/admin/users", data = "<user_request>")]
+async fn create_user(
+    mut users_store: UsersStore,
+    _admin: AdminUser,
+    user_request: Json<NewUserRequest>,
+) -> ArgentApiResult<User> {
+    let user = user_request.into_inner().get()?;
+    check_email_free(&mut users_store, &user).await?;
+    users_store.add_user(user.clone()).await?;
+    ArgentApiResult::new(user)
+}
+
+#[patch("/admin/users/<id>", data = "<user_request>")]
+async fn update_user(
+    mut users_store: UsersStore,
+    id: serde::uuid::Uuid,
+    _admin: AdminUser,
+    user_request: Json<UserUpdateRequest>,
+) -> ArgentApiResult<User> {
+    let user = get_user(&mut users_store, convert_uuid(&id)).await?;
+    let user = user.update_from_request(user_request.into_inner())?;
+    check_email_free(&mut users_store, &user).await?;
+    users_store.update_user(&user).await?;
+    ArgentApiResult::new(user)
+}
+
+#[delete("/admin/users/<id>")]
+async fn delete_user(
+    mut users_store: UsersStore,
+    id: serde::uuid::Uuid,
+    _admin: AdminUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let user = get_user(&mut users_store, convert_uuid(&id)).await?;
+    users_store.delete_user(user.id).await?;
+    ArgentApiResult::new_ok()
+}
+
+#[get("/admin/groups")]
+async fn get_groups(mut group_store: GroupStore, _admin: AdminUser) -> ArgentApiResult<Vec<Group>> {
+    let groups = group_store.get_groups().await?;
+    ArgentApiResult::new(groups)
+}
+
+#[post("/admin/groups", data = "<group_request>")]
+async fn create_group(
+    mut group_store: GroupStore,
+    _admin: AdminUser,
+    group_request: Json<GroupRequest>,
+) -> ArgentApiResult<Group> {
+    let group_request = group_request.into_inner();
+    let owner = group_request
+        .owner_id
+        .map(|owner| parse_uuid(&owner, Status::BadRequest))
+        .transpose()?;
+    let group = Group::new(group_request.name)?;
+    group_store.create_group(&group, owner).await?;
+    ArgentApiResult::new(group)
+}
+
+#[patch("/admin/groups/<id>", data = "<group_request>")]
+async fn update_group(
+    mut group_store: GroupStore,
+    id: serde::uuid::Uuid,
+    _admin: AdminUser,
+    group_request: Json<GroupUpdateRequest>,
+) -> ArgentApiResult<Group> {
+    let group = group_store.get_group(convert_uuid(&id)).await?;
+    let group = group.rename(group_request.into_inner().name)?;
+    group_store.update_group(&group).await?;
+    ArgentApiResult::new(group)
+}
+
+#[delete("/admin/groups/<id>")]
+async fn delete_group(
+    mut group_store: GroupStore,
+    id: serde::uuid::Uuid,
+    _admin: AdminUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let revoked = group_store
+        .delete_group(convert_uuid(&id))
+        .await?
+        .ok_or_else(|| ArgentError::not_found_msg("group not found"))?;
+    for share in revoked {
+        events.publish(ChecklistEvent::new(
+            share.checklist,
+            ChecklistChange::Unshared {
+                user: share.argent_user,
+            },
+        ));
+    }
+    ArgentApiResult::new_ok()
+}
+
+async fn get_user(users_store: &mut UsersStore, id: Uuid) -> ArgentResult<User> {
+    users_store
+        .find_user(id)
+        .await?
+        .ok_or_else(ArgentError::not_found)
+}
+
+async fn check_email_free(users_store: &mut UsersStore, user: &User) -> ArgentResult<()> {
+    match users_store.find_user_for_email(&user.email).await? {
+        Some(other) if other.id != user.id => Err(ArgentError::new(
+            "a user with that email already exists",
+            Status::Conflict,
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_users,
+        create_user,
+        update_user,
+        delete_user,
+        get_groups,
+        create_group,
+        update_group,
+        delete_group
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{http::Status, serde::json::json};
+
+    use crate::{
+        data::users::models::UserRole,
+        testing::{add_user, add_user_with_role, delete_as, get_as, patch_as, post_as, TestApp},
+    };
+
+    #[rocket::async_test]
+    async fn only_admins_may_manage_users() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+
+        let (status, _) = get_as(client, &user, "/api/v1/admin/users").await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/admin/users",
+            json!({ "name": "new", "email": "new@test.argent", "role": "Admin" }),
+        )
+        .await;
+        assert_eq!(status, Status::Forbidden);
+        let response = client.get("/api/v1/admin/users").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn admin_creates_updates_and_deletes_users() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+
+        let (status, created) = post_as(
+            client,
+            &admin,
+            "/api/v1/admin/users",
+            json!({ "name": "new", "email": "new@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let uri = format!("/api/v1/admin/users/{}", created["id"].as_str().unwrap());
+
+        let (status, updated) = patch_as(
+            client,
+            &admin,
+            &uri,
+            json!({ "name": "renamed", "role": "Admin" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(updated["name"], "renamed");
+        assert_eq!(updated["email"], "new@test.argent");
+        assert_eq!(updated["role"], "Admin");
+
+        let (_, users) = get_as(client, &admin, "/api/v1/admin/users").await;
+        assert!(users
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|user| user["name"] == "renamed"));
+
+        let (status, _) = delete_as(client, &admin, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = delete_as(client, &admin, &uri).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn emails_have_to_be_unique() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let user = add_user(client, "user").await;
+
+        let (status, _) = post_as(
+            client,
+            &admin,
+            "/api/v1/admin/users",
+            json!({ "name": "copy", "email": user.email, "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Conflict);
+        let (status, _) = patch_as(
+            client,
+            &admin,
+            &format!("/api/v1/admin/users/{}", admin.id),
+            json!({ "email": user.email }),
+        )
+        .await;
+        assert_eq!(status, Status::Conflict);
+        let (status, _) = post_as(
+            client,
+            &admin,
+            "/api/v1/admin/users",
+            json!({ "name": "shouting", "email": user.email.to_uppercase(), "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Conflict);
+    }
+
+    #[rocket::async_test]
+    async fn the_last_admin_cannot_be_demoted_or_deleted() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let uri = format!("/api/v1/admin/users/{}", admin.id);
+
+        let (status, _) = patch_as(client, &admin, &uri, json!({ "role": "User" })).await;
+        assert_eq!(status, Status::Conflict);
+        let (status, _) = delete_as(client, &admin, &uri).await;
+        assert_eq!(status, Status::Conflict);
+
+        // With another admin around it is fine
+        add_user_with_role(client, "other admin", UserRole::Admin).await;
+        let (status, _) = patch_as(client, &admin, &uri, json!({ "role": "User" })).await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = get_as(client, &admin, "/api/v1/admin/users").await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn admin_manages_groups() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let owner = add_user(client, "owner").await;
+
+        let (status, _) = post_as(
+            client,
+            &owner,
+            "/api/v1/admin/groups",
+            json!({ "name": "home" }),
+        )
+        .await;
+        assert_eq!(status, Status::Forbidden);
+
+        let (status, group) = post_as(
+            client,
+            &admin,
+            "/api/v1/admin/groups",
+            json!({ "name": "home", "ownerId": owner.id.to_string() }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let uri = format!("/api/v1/admin/groups/{}", group["id"].as_str().unwrap());
+
+        let (_, groups) = get_as(client, &owner, "/api/v1/groups").await;
+        assert_eq!(groups[0]["name"], "home");
+        assert_eq!(groups[0]["role"], "Owner");
+
+        let (status, renamed) = patch_as(client, &admin, &uri, json!({ "name": "house" })).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(renamed["name"], "house");
+
+        let (status, _) = delete_as(client, &admin, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let (_, groups) = get_as(client, &admin, "/api/v1/admin/groups").await;
+        assert!(groups.as_array().unwrap().is_empty());
+        let (status, _) = delete_as(client, &admin, &uri).await;
+        assert_eq!(status, Status::NotFound);
+    }
+}
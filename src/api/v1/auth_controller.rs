@@ -1,41 +1,588 @@
-use rocket::{get, http::CookieJar, routes, Route, State};
+use rocket::{get, http::CookieJar, post, routes, Route, State};
 
+#[cfg(debug_assertions)]
+use crate::debugging::DevUsers;
 use crate::{
     api::{
         auth::{
-            cookie::{create_auth_cookie, create_expired_cookie},
+            cookie::{
+                create_auth_cookie, create_expired_cookie, create_expired_refresh_cookie,
+                create_refresh_cookie,
+            },
+            csrf::AllowedOrigin,
             google_verification::AuthenticatedGoogleMail,
+            jwt::decode_token,
         },
-        helpers::{ArgentApiResult, NewData, OkData},
+        helpers::{ArgentApiResult, ArgentResult, NewData, OkData, UserAgent},
     },
     config::AuthenticationConfig,
-    data::users::{models::User, store::UsersStore},
-    error::SimpleMessage,
+    data::{
+        invitations::store::InvitationStore,
+        secrets::hash_token,
+        sessions::{
+            events::{SessionBroadcast, SessionRevoked},
+            models::{RefreshToken, Rotation},
+            store::SessionStore,
+        },
+        users::{models::User, store::UsersStore},
+    },
+    error::{ArgentError, SimpleMessage},
 };
 
-#[get("/login")]
+/// Logs in with an ID token from the identity provider. The first login of
+/// someone without an account uses up their invitation, either one for their
+/// email or the one with the `invitation` code. Accounts are found by email,
+/// so emails the provider has not verified are refused.
+#[get("/login?<invitation>")]
+#[allow(clippy::too_many_arguments)]
 async fn login(
-    email: AuthenticatedGoogleMail,
+    identity: AuthenticatedGoogleMail,
+    invitation: Option<&str>,
     mut users_store: UsersStore,
+    mut invitation_store: InvitationStore,
+    mut session_store: SessionStore,
+    user_agent: UserAgent,
     cookies: &CookieJar<'_>,
     auth_config: &State<AuthenticationConfig>,
 ) -> ArgentApiResult<User> {
-    let user = users_store.get_user_for_email(&email.0).await?;
-    let auth_cookie = create_auth_cookie(&auth_config, &user);
-    cookies.add(auth_cookie);
+    let identity = identity.0;
+    if identity.email_verified == Some(false) {
+        return Err(ArgentError::forbidden_msg(
+            "Your email address has not been verified",
+        ));
+    }
+    let user = match users_store.find_user_for_email(&identity.email).await? {
+        Some(user) => user,
+        None => {
+            let name = identity
+                .name
+                .as_deref()
+                .or_else(|| identity.email.split('@').next())
+                .unwrap_or_default();
+            match invitation_store
+                .accept_invitation(&identity.email, name, invitation)
+                .await?
+            {
+                Some(user) => user,
+                // Another first login may have created the account meanwhile
+                None => users_store
+                    .find_user_for_email(&identity.email)
+                    .await?
+                    .ok_or_else(|| {
+                        ArgentError::forbidden_msg("You have not been invited to Argent")
+                    })?,
+            }
+        }
+    };
+    start_session(&user, &mut session_store, user_agent, cookies, auth_config).await?;
     ArgentApiResult::new(user)
 }
 
+/// Sets the access and refresh cookies of a new session
+async fn start_session(
+    user: &User,
+    session_store: &mut SessionStore,
+    user_agent: UserAgent,
+    cookies: &CookieJar<'_>,
+    auth_config: &AuthenticationConfig,
+) -> ArgentResult<()> {
+    let session = session_store
+        .create_session(user.id, user_agent.0.as_deref())
+        .await?;
+    let refresh_token = RefreshToken::new(session, user.id);
+    session_store.add_refresh_token(&refresh_token).await?;
+    cookies.add(create_auth_cookie(auth_config, user.id, session));
+    cookies.add(create_refresh_cookie(auth_config, &refresh_token));
+    Ok(())
+}
+
+/// Logs in as one of the users of the dev seed file without the identity
+/// provider, for developing offline. Not compiled into release builds.
+#[cfg(debug_assertions)]
+#[get("/auth/dev-login?<email>")]
+#[allow(clippy::too_many_arguments)]
+async fn dev_login(
+    email: &str,
+    dev_users: &State<DevUsers>,
+    mut users_store: UsersStore,
+    mut session_store: SessionStore,
+    user_agent: UserAgent,
+    cookies: &CookieJar<'_>,
+    auth_config: &State<AuthenticationConfig>,
+) -> ArgentApiResult<User> {
+    let not_found = || ArgentError::not_found_msg("No dev user with that email");
+    if !dev_users.contains(email) {
+        return Err(not_found());
+    }
+    let user = users_store
+        .find_user_for_email(email)
+        .await?
+        .ok_or_else(not_found)?;
+    start_session(&user, &mut session_store, user_agent, cookies, auth_config).await?;
+    ArgentApiResult::new(user)
+}
+
+/// Trades the refresh cookie for a new access cookie and a new refresh cookie
+#[post("/auth/refresh")]
+async fn refresh(
+    _origin: AllowedOrigin,
+    mut users_store: UsersStore,
+    mut session_store: SessionStore,
+    cookies: &CookieJar<'_>,
+    auth_config: &State<AuthenticationConfig>,
+    sessions: &State<SessionBroadcast>,
+) -> ArgentApiResult<User> {
+    let token = cookies
+        .get(&auth_config.refresh_cookie_name())
+        .map(|cookie| cookie.value().to_string())
+        .ok_or_else(|| ArgentError::unauthorized_msg("No refresh cookie"))?;
+    match session_store.rotate(&token).await? {
+        Rotation::Rotated(refresh_token) => {
+            let user = users_store.get_user(refresh_token.argent_user).await?;
+            cookies.add(create_auth_cookie(
+                auth_config,
+                user.id,
+                refresh_token.session,
+            ));
+            cookies.add(create_refresh_cookie(auth_config, &refresh_token));
+            ArgentApiResult::new(user)
+        }
+        Rotation::Reused { session } => {
+            sessions.publish(SessionRevoked::Session(session));
+            cookies.add(create_expired_cookie(auth_config));
+            cookies.add(create_expired_refresh_cookie(auth_config));
+            Err(ArgentError::unauthorized())
+        }
+        Rotation::Invalid => {
+            cookies.add(create_expired_cookie(auth_config));
+            cookies.add(create_expired_refresh_cookie(auth_config));
+            Err(ArgentError::unauthorized())
+        }
+    }
+}
+
+/// Ends the session of this browser, also works with an expired access cookie
 #[get("/logout")]
 async fn logout(
+    mut session_store: SessionStore,
     cookies: &CookieJar<'_>,
     auth_config: &State<AuthenticationConfig>,
+    sessions: &State<SessionBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
-    let auth_cookie = create_expired_cookie(&auth_config);
-    cookies.add(auth_cookie);
+    if let Some(refresh_cookie) = cookies.get(&auth_config.refresh_cookie_name()) {
+        let revoked = session_store
+            .revoke_session_for_token(&hash_token(refresh_cookie.value()))
+            .await?;
+        if let Some(session) = revoked {
+            sessions.publish(SessionRevoked::Session(session));
+        }
+    }
+    let claims = cookies
+        .get(&auth_config.cookie_name)
+        .and_then(|cookie| decode_token(cookie.value(), auth_config).ok());
+    if let Some(claims) = claims {
+        let revoked = session_store
+            .revoke_session(claims.session, claims.user_id)
+            .await?;
+        if revoked {
+            sessions.publish(SessionRevoked::Session(claims.session));
+        }
+    }
+    cookies.add(create_expired_cookie(auth_config));
+    cookies.add(create_expired_refresh_cookie(auth_config));
     ArgentApiResult::new_ok()
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![login, logout]
+    let routes = routes![login, refresh, logout];
+    #[cfg(debug_assertions)]
+    let routes = [routes, routes![dev_login]].concat();
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(debug_assertions)]
+    use rocket::figment::{
+        providers::{Format, Toml},
+        Figment,
+    };
+    use rocket::{
+        http::{Cookie, Header, Status},
+        local::asynchronous::{Client, LocalResponse},
+        time::{ext::NumericalDuration, OffsetDateTime},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        api::auth::jwt::{decode_token, generate_token},
+        config::AuthenticationConfig,
+        data::{secrets::hash_token, users::models::User},
+        testing::{
+            add_user, connection, google_token, id_token, id_token_without_claim,
+            into_status_and_json, origin, test_auth_config, TestApp, IDP_AUDIENCE, IDP_ISSUER,
+        },
+    };
+    #[cfg(debug_assertions)]
+    use crate::{
+        data::users::models::UserRole,
+        debugging::{DevSeed, DevUsers},
+        testing::add_user_with_role,
+    };
+
+    fn cookie_value(response: &LocalResponse<'_>, name: &str) -> Option<String> {
+        response
+            .cookies()
+            .get(name)
+            .map(|cookie| cookie.value().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Logs in and returns the session and the refresh token
+    async fn login(client: &Client, user: &User) -> (Uuid, String) {
+        let token = google_token(&user.email);
+        let response = client
+            .get("/api/v1/login")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let auth_config = test_auth_config();
+        let access_token = cookie_value(&response, &auth_config.cookie_name).unwrap();
+        let session = decode_token(&access_token, &auth_config).unwrap().session;
+        let refresh_token =
+            cookie_value(&response, &auth_config.refresh_cookie_name()).expect("No refresh cookie");
+        (session, refresh_token)
+    }
+
+    async fn refresh<'c>(client: &'c Client, refresh_token: &str) -> LocalResponse<'c> {
+        client
+            .post("/api/v1/auth/refresh")
+            .header(origin())
+            .cookie(Cookie::new(
+                test_auth_config().refresh_cookie_name(),
+                refresh_token.to_string(),
+            ))
+            .dispatch()
+            .await
+    }
+
+    /// An access cookie for the session that expires in `seconds`
+    fn access_cookie_expiring_in(user: &User, session: Uuid, seconds: i64) -> Cookie<'static> {
+        let auth_config = test_auth_config();
+        let exp = OffsetDateTime::now_utc().saturating_add(seconds.seconds());
+        let token = generate_token(
+            user.id,
+            session,
+            &auth_config,
+            exp.unix_timestamp() as usize,
+        );
+        Cookie::new(auth_config.cookie_name, token)
+    }
+
+    async fn login_status(app: &TestApp, token: &str) -> Status {
+        app.client()
+            .get("/api/v1/login")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[rocket::async_test]
+    async fn login_with_google_token_starts_a_session() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+
+        let token = google_token(&user.email);
+        let response = client
+            .get("/api/v1/login")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+        let cookie_name = test_auth_config().cookie_name;
+        assert!(response.cookies().get(&cookie_name).is_some());
+        let (status, body) = into_status_and_json(response).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["id"], user.id.to_string());
+
+        // The tracked client keeps the session cookie
+        let (status, me) = into_status_and_json(client.get("/api/v1/me").dispatch().await).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["email"], user.email);
+    }
+
+    #[rocket::async_test]
+    async fn login_requires_google_token() {
+        let app = TestApp::new().await;
+
+        let response = app.client().get("/api/v1/login").dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn login_rejects_tokens_from_other_issuers() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let token = id_token(&user.email, "https://evil.example", IDP_AUDIENCE);
+        assert_eq!(login_status(&app, &token).await, Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn login_rejects_tokens_without_issuer_or_audience() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        for claim in ["iss", "aud"] {
+            let token = id_token_without_claim(&user.email, claim);
+            assert_eq!(login_status(&app, &token).await, Status::Unauthorized);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn login_rejects_tokens_for_other_audiences() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let token = id_token(&user.email, IDP_ISSUER, "some-other-client");
+        assert_eq!(login_status(&app, &token).await, Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn refresh_rotates_the_refresh_token() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (_, refresh_token) = login(client, &user).await;
+
+        let response = refresh(client, &refresh_token).await;
+        let auth_config = test_auth_config();
+        assert!(cookie_value(&response, &auth_config.cookie_name).is_some());
+        let next_token = cookie_value(&response, &auth_config.refresh_cookie_name())
+            .expect("No new refresh cookie");
+        assert_ne!(next_token, refresh_token);
+        let (status, body) = into_status_and_json(response).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["id"], user.id.to_string());
+
+        let response = refresh(client, &next_token).await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn retried_refresh_gets_the_same_successor() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (_, refresh_token) = login(client, &user).await;
+        let refresh_cookie_name = test_auth_config().refresh_cookie_name();
+        let response = refresh(client, &refresh_token).await;
+        let next_token = cookie_value(&response, &refresh_cookie_name).unwrap();
+
+        let response = refresh(client, &refresh_token).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            cookie_value(&response, &refresh_cookie_name),
+            Some(next_token.clone())
+        );
+        let response = refresh(client, &next_token).await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn reusing_a_refresh_token_revokes_its_successors() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (_, refresh_token) = login(client, &user).await;
+        let response = refresh(client, &refresh_token).await;
+        let next_token =
+            cookie_value(&response, &test_auth_config().refresh_cookie_name()).unwrap();
+        sqlx::query(
+            "UPDATE refresh_tokens SET used_at = now() - INTERVAL '1 minute' WHERE token_hash = $1",
+        )
+        .bind(hash_token(&refresh_token))
+        .execute(&mut connection(client).await)
+        .await
+        .unwrap();
+
+        let response = refresh(client, &refresh_token).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = refresh(client, &next_token).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn refresh_requires_a_known_token() {
+        let app = TestApp::new().await;
+
+        let response = refresh(app.client(), "made-up").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = app
+            .client()
+            .post("/api/v1/auth/refresh")
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn logout_revokes_the_refresh_token() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (_, refresh_token) = login(client, &user).await;
+
+        let response = client.get("/api/v1/logout").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = refresh(client, &refresh_token).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn logout_cookies_are_strict_in_secure_mode() {
+        let app = TestApp::with_auth_config(AuthenticationConfig {
+            secure_cookie: true,
+            ..test_auth_config()
+        })
+        .await;
+
+        let response = app.client().get("/api/v1/logout").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let cookies = response.headers().get("Set-Cookie").collect::<Vec<_>>();
+        assert_eq!(cookies.len(), 2);
+        for cookie in cookies {
+            assert!(cookie.contains("SameSite=Strict"), "{}", cookie);
+            assert!(cookie.contains("Secure"), "{}", cookie);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn access_cookie_is_renewed_close_to_expiry() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (session, refresh_token) = login(client, &user).await;
+        let auth_config = test_auth_config();
+
+        let response = client
+            .get("/api/v1/me")
+            .cookie(access_cookie_expiring_in(&user, session, 60))
+            .cookie(Cookie::new(
+                auth_config.refresh_cookie_name(),
+                refresh_token.clone(),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(cookie_value(&response, &auth_config.cookie_name).is_some());
+
+        // Not yet close to expiry
+        let response = client
+            .get("/api/v1/me")
+            .cookie(access_cookie_expiring_in(&user, session, 25 * 60))
+            .cookie(Cookie::new(
+                auth_config.refresh_cookie_name(),
+                refresh_token,
+            ))
+            .dispatch()
+            .await;
+        assert!(cookie_value(&response, &auth_config.cookie_name).is_none());
+    }
+
+    #[rocket::async_test]
+    async fn access_cookie_is_not_renewed_without_active_refresh_token() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (session, refresh_token) = login(client, &user).await;
+        let auth_config = test_auth_config();
+        refresh(client, &refresh_token).await;
+
+        let response = client
+            .get("/api/v1/me")
+            .cookie(access_cookie_expiring_in(&user, session, 60))
+            .cookie(Cookie::new(
+                auth_config.refresh_cookie_name(),
+                refresh_token,
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(cookie_value(&response, &auth_config.cookie_name).is_none());
+    }
+
+    #[rocket::async_test]
+    async fn logout_revokes_copies_of_the_access_cookie() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (session, _) = login(client, &user).await;
+        let copied_cookie = access_cookie_expiring_in(&user, session, 25 * 60);
+
+        client.get("/api/v1/logout").dispatch().await;
+
+        let response = client
+            .get("/api/v1/me")
+            .cookie(copied_cookie)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn logout_ends_the_session() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let token = google_token(&user.email);
+        client
+            .get("/api/v1/login")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+
+        let response = client.get("/api/v1/logout").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get("/api/v1/me").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[cfg(debug_assertions)]
+    #[rocket::async_test]
+    async fn dev_login_only_works_for_seeded_users() {
+        let email = format!("{}@test.argent", Uuid::new_v4());
+        let seed: DevSeed = Figment::from(Toml::string(&format!(
+            "[[users]]\nemail = \"{}\"\nname = \"Dev\"",
+            email
+        )))
+        .extract()
+        .unwrap();
+        let app = TestApp::with_dev_users(DevUsers::from_seed(&seed)).await;
+        let client = app.client();
+        seed.apply(&mut connection(client).await).await.unwrap();
+
+        let response = client
+            .get(format!("/api/v1/auth/dev-login?email={}", email))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let (status, me) = into_status_and_json(client.get("/api/v1/me").dispatch().await).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["email"], email);
+
+        let other = add_user_with_role(client, "admin", UserRole::Admin).await;
+        for email in [other.email.as_str(), "nobody@test.argent"] {
+            let response = client
+                .get(format!("/api/v1/auth/dev-login?email={}", email))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+    }
 }
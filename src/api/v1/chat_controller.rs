@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+
+use rocket::{
+    get,
+    http::Status,
+    post,
+    response::stream::{Event, EventStream},
+    routes,
+    serde::json::Json,
+    tokio::{select, sync::broadcast::error::RecvError},
+    Route, Shutdown, State,
+};
+
+use crate::{
+    api::{
+        auth::user_guard::AuthenticatedUser,
+        helpers::{parse_uuid, ArgentApiResult, ArgentResult, LastEventId, NewData},
+    },
+    data::{
+        chat::{
+            models::{ChatBroadcast, ChatHistory, ChatMessage, ChatMessageRequest, HistoryCursor},
+            store::ChatStore,
+        },
+        sessions::events::SessionBroadcast,
+    },
+    error::ArgentError,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+const MAX_MISSED_MESSAGES: i64 = 500;
+
+#[get("/chat/messages?<before>&<limit>")]
+async fn get_messages(
+    mut chat_store: ChatStore,
+    _user: AuthenticatedUser,
+    before: Option<&str>,
+    limit: Option<i64>,
+) -> ArgentApiResult<ChatHistory> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ArgentError::bad_request_msg(
+            "limit must be between 1 and 200",
+        ));
+    }
+    let before = before.map(HistoryCursor::decode).transpose()?;
+    let history = chat_store.get_history(before, limit).await?;
+    ArgentApiResult::new(history)
+}
+
+#[post("/chat/messages", data = "<message_request>")]
+async fn post_message(
+    mut chat_store: ChatStore,
+    user: AuthenticatedUser,
+    message_request: Json<ChatMessageRequest>,
+    chat_broadcast: &State<ChatBroadcast>,
+) -> ArgentApiResult<ChatMessage> {
+    let message = message_request.into_inner().get(user.get())?;
+    chat_store.add_message(&message).await?;
+    chat_broadcast.publish(message.clone());
+    ArgentApiResult::new(message)
+}
+
+fn message_event(message: &ChatMessage) -> Event {
+    Event::json(message).id(message.id.to_string())
+}
+
+#[get("/chat/stream")]
+async fn stream(
+    mut chat_store: ChatStore,
+    user: AuthenticatedUser,
+    last_event_id: LastEventId,
+    chat_broadcast: &State<ChatBroadcast>,
+    sessions: &State<SessionBroadcast>,
+    mut shutdown: Shutdown,
+) -> ArgentResult<EventStream![]> {
+    let session = user.session();
+    let user_id = user.get().id;
+    let mut revocations = sessions.subscribe();
+    // Subscribe before catching up so nothing is lost in between,
+    // live messages that were part of the catch up are skipped below
+    let mut receiver = chat_broadcast.subscribe();
+    let missed = match last_event_id.0 {
+        Some(last_event_id) => {
+            let last_seen = parse_uuid(&last_event_id, Status::BadRequest)?;
+            chat_store
+                .get_messages_after(last_seen, MAX_MISSED_MESSAGES)
+                .await?
+        }
+        None => Vec::new(),
+    };
+    drop(chat_store);
+
+    Ok(EventStream! {
+        let sent = missed.iter().map(|message| message.id).collect::<HashSet<_>>();
+        for message in missed.iter() {
+            yield message_event(message);
+        }
+        // More were missed than fit in one catch up, the client reconnects
+        // from the last of them for the rest
+        if missed.len() as i64 == MAX_MISSED_MESSAGES {
+            return;
+        }
+        loop {
+            let message = select! {
+                message = receiver.recv() => match message {
+                    Ok(message) => message,
+                    // Messages were dropped, the client reconnects with
+                    // Last-Event-ID and catches up from the database
+                    Err(RecvError::Closed | RecvError::Lagged(_)) => break,
+                },
+                revoked = revocations.recv() => match revoked {
+                    Ok(revoked) if revoked.ends(session, user_id) => break,
+                    Ok(_) => continue,
+                    // Revocations were missed, the client reconnects and
+                    // its session is checked again
+                    Err(_) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            if !sent.contains(&message.id) {
+                yield message_event(&message);
+            }
+        }
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![get_messages, post_message, stream]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rocket::{
+        http::{Header, Status},
+        serde::json::serde_json::json,
+        tokio::time::{timeout, Duration},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        data::{
+            chat::models::{
+                millis_to_primitive_datetime, ChatBroadcast, ChatMessage, HistoryCursor,
+            },
+            users::models::User,
+        },
+        testing::{add_user, auth_cookie, connection, get_as, post_as, TestApp},
+    };
+
+    use super::MAX_MISSED_MESSAGES;
+
+    async fn history(app: &TestApp, user: &User, query: &str) -> (Vec<String>, Option<String>) {
+        let (status, body) = get_as(
+            app.client(),
+            user,
+            &format!("/api/v1/chat/messages?{}", query),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let messages = body["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["messageText"].as_str().unwrap().to_string())
+            .collect();
+        (messages, body["nextCursor"].as_str().map(String::from))
+    }
+
+    #[rocket::async_test]
+    async fn history_is_newest_first() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        for text in ["one", "two", "three"] {
+            let (status, _) = post_as(
+                app.client(),
+                &user,
+                "/api/v1/chat/messages",
+                json!({ "messageText": text }),
+            )
+            .await;
+            assert_eq!(status, Status::Ok);
+        }
+
+        let (messages, next_cursor) = history(&app, &user, "limit=2").await;
+        assert_eq!(messages, ["three", "two"]);
+        let next_cursor = next_cursor.unwrap();
+        let (messages, next_cursor) =
+            history(&app, &user, &format!("limit=2&before={}", next_cursor)).await;
+        assert_eq!(messages, ["one"]);
+        assert_eq!(next_cursor, None);
+    }
+
+    #[rocket::async_test]
+    async fn history_pages_through_messages_in_the_same_millisecond() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let mut conn = connection(app.client()).await;
+        let created_date = millis_to_primitive_datetime(1_650_000_000_000).unwrap();
+        for i in 0..5 {
+            sqlx::query(
+                "INSERT INTO chat_messages (id, sender, sender_id, message_text, created_date)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(&user.name)
+            .bind(user.id)
+            .bind(format!("message {}", i))
+            .bind(created_date)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        }
+
+        let mut seen = HashSet::new();
+        let mut query = String::from("limit=2");
+        loop {
+            let (messages, next_cursor) = history(&app, &user, &query).await;
+            seen.extend(messages);
+            match next_cursor {
+                Some(cursor) => query = format!("limit=2&before={}", cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 5);
+    }
+
+    #[rocket::async_test]
+    async fn history_rejects_invalid_cursors() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let out_of_range = HistoryCursor {
+            created_date: 9_000_000_000_000_000,
+            id: Uuid::new_v4(),
+        }
+        .encode();
+
+        for cursor in ["9000000000000000", "not-a-cursor", &out_of_range] {
+            let (status, _) = get_as(
+                app.client(),
+                &user,
+                &format!("/api/v1/chat/messages?before={}", cursor),
+            )
+            .await;
+            assert_eq!(status, Status::BadRequest, "{}", cursor);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn stream_ends_when_the_client_falls_behind() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let response = client
+            .get("/api/v1/chat/stream")
+            .cookie(auth_cookie(client, &user).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let broadcast = client.rocket().state::<ChatBroadcast>().unwrap();
+        for i in 0..2000 {
+            broadcast.publish(ChatMessage {
+                id: Uuid::new_v4(),
+                sender: user.name.clone(),
+                sender_id: user.id,
+                message_text: format!("message {}", i),
+                created_date: 0,
+            });
+        }
+        let body = timeout(Duration::from_secs(5), response.into_string())
+            .await
+            .expect("The stream should end");
+        assert!(body.is_some());
+    }
+
+    /// The ids of the events in a stream that has ended
+    async fn stream_ids(app: &TestApp, user: &User, last_event_id: Uuid) -> Vec<Uuid> {
+        let client = app.client();
+        let response = client
+            .get("/api/v1/chat/stream")
+            .cookie(auth_cookie(client, user).await)
+            .header(Header::new("Last-Event-ID", last_event_id.to_string()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let body = timeout(Duration::from_secs(5), response.into_string())
+            .await
+            .expect("The stream should end")
+            .unwrap();
+        body.lines()
+            .filter_map(|line| line.strip_prefix("id:"))
+            .map(|id| Uuid::parse_str(id.trim()).unwrap())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn stream_catches_up_on_more_than_fits_in_one_go() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let mut conn = connection(app.client()).await;
+        let missed_count = MAX_MISSED_MESSAGES as usize * 2;
+        let mut ids = Vec::new();
+        for i in 0..=missed_count {
+            let id = Uuid::new_v4();
+            sqlx::query(
+                "INSERT INTO chat_messages (id, sender, sender_id, message_text, created_date)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(id)
+            .bind(&user.name)
+            .bind(user.id)
+            .bind(format!("message {}", i))
+            .bind(millis_to_primitive_datetime(1_650_000_000_000 + i as i64).unwrap())
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        let mut received = Vec::new();
+        while received.len() < missed_count {
+            let last_seen = received.last().copied().unwrap_or(ids[0]);
+            let page = stream_ids(&app, &user, last_seen).await;
+            assert_eq!(page.len(), MAX_MISSED_MESSAGES as usize);
+            received.extend(page);
+        }
+        assert_eq!(received, ids[1..]);
+    }
+
+    #[rocket::async_test]
+    async fn stream_catches_up_in_the_order_messages_were_added() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let mut conn = connection(app.client()).await;
+        let mut ids = Vec::new();
+        for i in 0..=MAX_MISSED_MESSAGES {
+            // Pairs of messages share a millisecond, and each pair was stamped
+            // before the one added ahead of it, like a slow commit would be
+            let id = Uuid::new_v4();
+            sqlx::query(
+                "INSERT INTO chat_messages (id, sender, sender_id, message_text, created_date)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(id)
+            .bind(&user.name)
+            .bind(user.id)
+            .bind(format!("message {}", i))
+            .bind(millis_to_primitive_datetime(1_650_000_000_000 - i / 2).unwrap())
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        assert_eq!(stream_ids(&app, &user, ids[0]).await, ids[1..]);
+    }
+}
@@ -7,17 +7,30 @@ use crate::{
     },
     data::{
         checklists::{
+            events::{ChecklistBroadcast, ChecklistChange, ChecklistEvent},
             models::{
-                AccessType, Checklist, ChecklistItem, ChecklistItemRequest, ChecklistRequest,
-                ShareRequest, UserAccess,
+                AccessType, Checklist, ChecklistItem, ChecklistItemRequest,
+                ChecklistItemUpdateRequest, ChecklistRequest, MoveItemRequest, ShareRequest,
+                UserAccess,
             },
             store::ChecklistStore,
         },
+        groups::store::GroupStore,
+        sessions::events::SessionBroadcast,
         users::models::User,
     },
     error::{ArgentError, SimpleMessage},
 };
-use rocket::{delete, get, http::Status, post, routes, serde, serde::json::Json, Route};
+use rocket::{
+    delete, get,
+    http::Status,
+    patch, post,
+    response::stream::{Event, EventStream},
+    routes, serde,
+    serde::json::Json,
+    tokio::{select, sync::broadcast::error::RecvError},
+    Route, Shutdown, State,
+};
 use uuid::Uuid;
 
 #[get("/checklists")]
@@ -49,7 +62,7 @@ async fn get_checklist_items(
     user: AuthenticatedUser,
 ) -> ArgentApiResult<Vec<ChecklistItem>> {
     let checklist_id = convert_uuid(&id);
-    check_access(&mut checklists_store, checklist_id, user.get()).await?;
+    check_read_access(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store
         .get_checklist_items(checklist_id)
         .await
@@ -61,10 +74,15 @@ async fn create_checklistitem(
     mut checklists_store: ChecklistStore,
     checklistitem_request: Json<ChecklistItemRequest>,
     user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
-    let item = checklistitem_request.into_inner().get()?;
-    check_access(&mut checklists_store, item.checklist, user.get()).await?;
-    checklists_store.add_item(item).await?;
+    let mut item = checklistitem_request.into_inner().get()?;
+    check_write_access(&mut checklists_store, item.checklist, user.get()).await?;
+    item.position = checklists_store.add_item(&item).await?;
+    events.publish(ChecklistEvent::new(
+        item.checklist,
+        ChecklistChange::ItemCreated { item },
+    ));
     ArgentApiResult::new_ok()
 }
 
@@ -72,10 +90,20 @@ async fn create_checklistitem(
 async fn set_item_done(
     mut checklists_store: ChecklistStore,
     id: serde::uuid::Uuid,
-    _user: AuthenticatedUser,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let item_id = convert_uuid(&id);
+    let checklist_id = checklists_store.get_checklist_for_item(item_id).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store.set_item_done(item_id, true).await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::ItemDone {
+            item: item_id,
+            done: true,
+        },
+    ));
     ArgentApiResult::new_ok()
 }
 
@@ -83,10 +111,116 @@ async fn set_item_done(
 async fn set_item_not_done(
     mut checklists_store: ChecklistStore,
     id: serde::uuid::Uuid,
-    _user: AuthenticatedUser,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let item_id = convert_uuid(&id);
+    let checklist_id = checklists_store.get_checklist_for_item(item_id).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store.set_item_done(item_id, false).await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::ItemDone {
+            item: item_id,
+            done: false,
+        },
+    ));
+    ArgentApiResult::new_ok()
+}
+
+#[patch("/checklistitems/<id>", data = "<update_request>")]
+async fn update_checklistitem(
+    mut checklists_store: ChecklistStore,
+    id: serde::uuid::Uuid,
+    update_request: Json<ChecklistItemUpdateRequest>,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let item_id = convert_uuid(&id);
+    let title = update_request.into_inner().title;
+    let checklist_id = checklists_store.get_checklist_for_item(item_id).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
+    checklists_store.set_item_title(item_id, &title).await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::ItemRenamed {
+            item: item_id,
+            title,
+        },
+    ));
+    ArgentApiResult::new_ok()
+}
+
+#[delete("/checklistitems/<id>")]
+async fn delete_checklistitem(
+    mut checklists_store: ChecklistStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let item_id = convert_uuid(&id);
+    let checklist_id = checklists_store.get_checklist_for_item(item_id).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
+    checklists_store.delete_item(item_id).await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::ItemDeleted { item: item_id },
+    ));
+    ArgentApiResult::new_ok()
+}
+
+#[post("/checklistitems/<id>/move", data = "<move_request>")]
+async fn move_checklistitem(
+    mut checklists_store: ChecklistStore,
+    id: serde::uuid::Uuid,
+    move_request: Json<MoveItemRequest>,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let item_id = convert_uuid(&id);
+    let (anchor, before_anchor) = match move_request.into_inner() {
+        MoveItemRequest::Before(anchor) => (anchor, true),
+        MoveItemRequest::After(anchor) => (anchor, false),
+    };
+    let anchor_id = parse_uuid(&anchor, Status::BadRequest)?;
+    if anchor_id == item_id {
+        return Err(ArgentError::bad_request_msg(
+            "cannot move an item next to itself",
+        ));
+    }
+    let checklist_id = checklists_store.get_checklist_for_item(item_id).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
+    let position = checklists_store
+        .move_item(item_id, checklist_id, anchor_id, before_anchor)
+        .await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::ItemMoved {
+            item: item_id,
+            position,
+        },
+    ));
+    ArgentApiResult::new_ok()
+}
+
+#[patch("/checklists/<id>", data = "<checklist_request>")]
+async fn rename_checklist(
+    mut checklists_store: ChecklistStore,
+    id: serde::uuid::Uuid,
+    checklist_request: Json<ChecklistRequest>,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let checklist_id = convert_uuid(&id);
+    let name = checklist_request.into_inner().name;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
+    checklists_store
+        .rename_checklist(checklist_id, &name)
+        .await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::Renamed { name },
+    ));
     ArgentApiResult::new_ok()
 }
 
@@ -95,10 +229,12 @@ async fn delete_checklist(
     mut checklists_store: ChecklistStore,
     id: serde::uuid::Uuid,
     user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let checklist_id = convert_uuid(&id);
     check_owner(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store.delete_checklist(checklist_id).await?;
+    events.publish(ChecklistEvent::new(checklist_id, ChecklistChange::Deleted));
     ArgentApiResult::new_ok()
 }
 
@@ -109,7 +245,7 @@ async fn get_checklist(
     user: AuthenticatedUser,
 ) -> ArgentApiResult<Checklist> {
     let checklist_id = convert_uuid(&id);
-    check_access(&mut checklists_store, checklist_id, user.get()).await?;
+    check_read_access(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store
         .get_checklist_by_id(checklist_id)
         .await
@@ -121,27 +257,57 @@ async fn clear_done(
     mut checklists_store: ChecklistStore,
     id: serde::uuid::Uuid,
     user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let checklist_id = convert_uuid(&id);
-    check_access(&mut checklists_store, checklist_id, user.get()).await?;
+    check_write_access(&mut checklists_store, checklist_id, user.get()).await?;
     checklists_store.clear_done(checklist_id).await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::DoneCleared,
+    ));
     ArgentApiResult::new_ok()
 }
 
 #[post("/checklists/<id>/share", data = "<share_req>")]
 async fn share(
     mut checklists_store: ChecklistStore,
+    mut group_store: GroupStore,
     id: serde::uuid::Uuid,
     user: AuthenticatedUser,
     share_req: Json<ShareRequest>,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let checklist_id = convert_uuid(&id);
     let share_req = share_req.into_inner();
     let user_id = parse_uuid(&share_req.user_id, Status::BadRequest)?;
-    check_owner(&mut checklists_store, checklist_id, user.get()).await?;
+    if share_req.access_type == AccessType::None {
+        return Err(ArgentError::bad_request_msg(
+            "access type must be Owner, Editor or Viewer",
+        ));
+    }
+    let user = user.get();
+    check_owner(&mut checklists_store, checklist_id, user.clone()).await?;
+    if user_id == user.id {
+        return Err(ArgentError::bad_request_msg(
+            "you cannot share a checklist with yourself",
+        ));
+    }
+    if !group_store.shares_group(user.id, user_id).await? {
+        return Err(ArgentError::forbidden_msg(
+            "checklists can only be shared with members of your groups",
+        ));
+    }
     checklists_store
         .add_user_access(checklist_id, user_id, share_req.access_type)
         .await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::Shared {
+            user: user_id,
+            access_type: share_req.access_type,
+        },
+    ));
     ArgentApiResult::new_ok()
 }
 
@@ -151,6 +317,7 @@ async fn un_share(
     id: serde::uuid::Uuid,
     user_id: serde::uuid::Uuid,
     user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
 ) -> ArgentApiResult<SimpleMessage> {
     let checklist_id = convert_uuid(&id);
     let user_id = convert_uuid(&user_id);
@@ -170,6 +337,10 @@ async fn un_share(
     checklists_store
         .remove_useraccess(checklist_id, user_id)
         .await?;
+    events.publish(ChecklistEvent::new(
+        checklist_id,
+        ChecklistChange::Unshared { user: user_id },
+    ));
     ArgentApiResult::new_ok()
 }
 
@@ -180,21 +351,87 @@ async fn get_users_for_checklist(
     user: AuthenticatedUser,
 ) -> ArgentApiResult<Vec<UserAccess>> {
     let checklist_id = convert_uuid(&id);
-    check_access(&mut checklists_store, checklist_id, user.get()).await?;
+    check_read_access(&mut checklists_store, checklist_id, user.get()).await?;
     let user_accesses = checklists_store
         .get_users_access_for_checklist(checklist_id)
         .await?;
     ArgentApiResult::new(user_accesses)
 }
 
-async fn check_access(
+#[get("/checklists/<id>/stream")]
+async fn stream(
+    mut checklists_store: ChecklistStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+    sessions: &State<SessionBroadcast>,
+    mut shutdown: Shutdown,
+) -> ArgentResult<EventStream![]> {
+    let checklist_id = convert_uuid(&id);
+    let session = user.session();
+    let user = user.get();
+    let user_id = user.id;
+    check_read_access(&mut checklists_store, checklist_id, user).await?;
+    drop(checklists_store);
+    let mut receiver = events.subscribe();
+    let mut revocations = sessions.subscribe();
+
+    Ok(EventStream! {
+        loop {
+            let event = select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => {
+                        yield Event::json(&ChecklistEvent::new(checklist_id, ChecklistChange::Resync));
+                        break;
+                    }
+                },
+                revoked = revocations.recv() => match revoked {
+                    Ok(revoked) if revoked.ends(session, user_id) => break,
+                    Ok(_) => continue,
+                    // Revocations were missed, the client reconnects and
+                    // its session is checked again
+                    Err(_) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            if event.checklist != checklist_id {
+                continue;
+            }
+            // Stop streaming once the user can no longer see the checklist
+            let lost_access = match event.change {
+                ChecklistChange::Deleted => true,
+                ChecklistChange::Unshared { user } => user == user_id,
+                _ => false,
+            };
+            yield Event::json(&event);
+            if lost_access {
+                break;
+            }
+        }
+    })
+}
+
+async fn check_read_access(
     checklists_store: &mut ChecklistStore,
     checklist_id: Uuid,
     user: User,
 ) -> ArgentResult<()> {
     match checklists_store.get_access_type(checklist_id, user).await? {
-        AccessType::Owner | AccessType::Editor => Ok(()),
-        AccessType::None => Err(ArgentError::forbidden()),
+        access_type if access_type.can_read() => Ok(()),
+        _ => Err(ArgentError::forbidden()),
+    }
+}
+
+async fn check_write_access(
+    checklists_store: &mut ChecklistStore,
+    checklist_id: Uuid,
+    user: User,
+) -> ArgentResult<()> {
+    match checklists_store.get_access_type(checklist_id, user).await? {
+        access_type if access_type.can_write() => Ok(()),
+        _ => Err(ArgentError::forbidden()),
     }
 }
 
@@ -217,11 +454,342 @@ pub fn checklist_routes() -> Vec<Route> {
         create_checklistitem,
         set_item_done,
         set_item_not_done,
+        update_checklistitem,
+        delete_checklistitem,
+        move_checklistitem,
+        rename_checklist,
         delete_checklist,
         get_checklist,
         clear_done,
         share,
         un_share,
-        get_users_for_checklist
+        get_users_for_checklist,
+        stream
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::Status,
+        local::asynchronous::Client,
+        serde::json::json,
+        tokio::time::{timeout, Duration},
+    };
+    use uuid::Uuid;
+
+    use crate::data::{
+        checklists::events::{ChecklistBroadcast, ChecklistChange, ChecklistEvent},
+        users::models::User,
+    };
+
+    use crate::testing::{
+        add_group, add_user, auth_cookie, create_checklist, create_item, delete_as, get_as,
+        patch_as, post_as, share_checklist, TestApp,
+    };
+
+    #[rocket::async_test]
+    async fn toggling_items_requires_write_access() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let stranger = add_user(client, "stranger").await;
+        let viewer = add_user(client, "viewer").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let item = create_item(client, &owner, &checklist, "milk").await;
+        add_group(client, &[&owner, &viewer]).await;
+        share_checklist(client, &owner, &checklist, &viewer, "Viewer").await;
+
+        for user in [&stranger, &viewer] {
+            for action in ["done", "not-done"] {
+                let uri = format!("/api/v1/checklistitems/{}/{}", item, action);
+                let (status, _) = post_as(client, user, &uri, json!({})).await;
+                assert_eq!(status, Status::Forbidden, "{} {}", user.name, action);
+            }
+        }
+
+        let (_, items) = get_as(
+            client,
+            &owner,
+            &format!("/api/v1/checklists/{}/items", checklist),
+        )
+        .await;
+        assert_eq!(items[0]["done"], false);
+    }
+
+    #[rocket::async_test]
+    async fn editors_can_toggle_items() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let editor = add_user(client, "editor").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let item = create_item(client, &owner, &checklist, "milk").await;
+        add_group(client, &[&owner, &editor]).await;
+        share_checklist(client, &owner, &checklist, &editor, "Editor").await;
+
+        let uri = format!("/api/v1/checklistitems/{}/done", item);
+        let (status, _) = post_as(client, &editor, &uri, json!({})).await;
+        assert_eq!(status, Status::Ok);
+
+        let (_, items) = get_as(
+            client,
+            &owner,
+            &format!("/api/v1/checklists/{}/items", checklist),
+        )
+        .await;
+        assert_eq!(items[0]["done"], true);
+    }
+
+    #[rocket::async_test]
+    async fn toggling_unknown_item_is_not_found() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+
+        let uri = format!("/api/v1/checklistitems/{}/done", Uuid::new_v4());
+        let (status, _) = post_as(client, &user, &uri, json!({})).await;
+        assert_eq!(status, Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn checklist_routes_reject_users_without_access() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let stranger = add_user(client, "stranger").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+
+        for uri in ["", "/items", "/users"] {
+            let uri = format!("/api/v1/checklists/{}{}", checklist, uri);
+            let (status, _) = get_as(client, &stranger, &uri).await;
+            assert_eq!(status, Status::Forbidden, "GET {}", uri);
+        }
+
+        let item = json!({ "title": "milk", "checklist": checklist });
+        let (status, _) = post_as(client, &stranger, "/api/v1/checklistitems", item).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/api/v1/checklists/{}/clear-done", checklist);
+        let (status, _) = post_as(client, &stranger, &uri, json!({})).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/api/v1/checklists/{}/share", checklist);
+        let share = json!({ "userId": stranger.id.to_string(), "accessType": "Owner" });
+        let (status, _) = post_as(client, &stranger, &uri, share).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/api/v1/checklists/{}", checklist);
+        let (status, _) = delete_as(client, &stranger, &uri).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn checklists_are_only_shared_within_groups() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let stranger = add_user(client, "stranger").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+
+        let uri = format!("/api/v1/checklists/{}/share", checklist);
+        let share = json!({ "userId": stranger.id.to_string(), "accessType": "Viewer" });
+        let (status, _) = post_as(client, &owner, &uri, share.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+
+        add_group(client, &[&owner, &stranger]).await;
+        let (status, _) = post_as(client, &owner, &uri, share).await;
+        assert_eq!(status, Status::Ok);
+
+        let share = json!({ "userId": owner.id.to_string(), "accessType": "Viewer" });
+        let (status, _) = post_as(client, &owner, &uri, share).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn viewers_can_read_but_not_write() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let viewer = add_user(client, "viewer").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        create_item(client, &owner, &checklist, "milk").await;
+        add_group(client, &[&owner, &viewer]).await;
+        share_checklist(client, &owner, &checklist, &viewer, "Viewer").await;
+
+        let uri = format!("/api/v1/checklists/{}/items", checklist);
+        let (status, items) = get_as(client, &viewer, &uri).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(items.as_array().unwrap().len(), 1);
+
+        let item = json!({ "title": "bread", "checklist": checklist });
+        let (status, _) = post_as(client, &viewer, "/api/v1/checklistitems", item).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/api/v1/checklists/{}/clear-done", checklist);
+        let (status, _) = post_as(client, &viewer, &uri, json!({})).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn editors_can_edit_and_delete_items() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let editor = add_user(client, "editor").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let milk = create_item(client, &owner, &checklist, "milk").await;
+        let bread = create_item(client, &owner, &checklist, "bread").await;
+        add_group(client, &[&owner, &editor]).await;
+        share_checklist(client, &owner, &checklist, &editor, "Editor").await;
+
+        let uri = format!("/api/v1/checklistitems/{}", milk);
+        let (status, _) = patch_as(client, &editor, &uri, json!({ "title": "oat milk" })).await;
+        assert_eq!(status, Status::Ok);
+        let uri = format!("/api/v1/checklistitems/{}", bread);
+        let (status, _) = delete_as(client, &editor, &uri).await;
+        assert_eq!(status, Status::Ok);
+
+        let uri = format!("/api/v1/checklists/{}/items", checklist);
+        let (_, items) = get_as(client, &owner, &uri).await;
+        let items = items.as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["title"], "oat milk");
+    }
+
+    #[rocket::async_test]
+    async fn viewers_cannot_edit_or_delete_items() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let viewer = add_user(client, "viewer").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let item = create_item(client, &owner, &checklist, "milk").await;
+        add_group(client, &[&owner, &viewer]).await;
+        share_checklist(client, &owner, &checklist, &viewer, "Viewer").await;
+
+        let uri = format!("/api/v1/checklistitems/{}", item);
+        let (status, _) = patch_as(client, &viewer, &uri, json!({ "title": "beer" })).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = delete_as(client, &viewer, &uri).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let uri = format!("/api/v1/checklists/{}", checklist);
+        let (status, _) = patch_as(client, &viewer, &uri, json!({ "name": "mine" })).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn owners_can_rename_checklist() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+
+        let uri = format!("/api/v1/checklists/{}", checklist);
+        let (status, _) = patch_as(client, &owner, &uri, json!({ "name": "food" })).await;
+        assert_eq!(status, Status::Ok);
+
+        let (_, list) = get_as(client, &owner, &uri).await;
+        assert_eq!(list["name"], "food");
+    }
+
+    async fn item_titles(client: &Client, user: &User, checklist: &str) -> Vec<String> {
+        let uri = format!("/api/v1/checklists/{}/items", checklist);
+        let (_, items) = get_as(client, user, &uri).await;
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["title"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn items_keep_their_manual_order() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let milk = create_item(client, &owner, &checklist, "milk").await;
+        let bread = create_item(client, &owner, &checklist, "bread").await;
+        let eggs = create_item(client, &owner, &checklist, "eggs").await;
+        assert_eq!(
+            item_titles(client, &owner, &checklist).await,
+            ["milk", "bread", "eggs"]
+        );
+
+        let uri = format!("/api/v1/checklistitems/{}/move", eggs);
+        let (status, _) = post_as(client, &owner, &uri, json!({ "before": milk })).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            item_titles(client, &owner, &checklist).await,
+            ["eggs", "milk", "bread"]
+        );
+
+        let uri = format!("/api/v1/checklistitems/{}/move", eggs);
+        let (status, _) = post_as(client, &owner, &uri, json!({ "after": milk })).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(
+            item_titles(client, &owner, &checklist).await,
+            ["milk", "eggs", "bread"]
+        );
+
+        // Keep squeezing into the same gap until the checklist has to be renumbered
+        let (mut first, mut second) = (bread, eggs);
+        for _ in 0..60 {
+            let uri = format!("/api/v1/checklistitems/{}/move", first);
+            let (status, _) = post_as(client, &owner, &uri, json!({ "after": milk })).await;
+            assert_eq!(status, Status::Ok);
+            std::mem::swap(&mut first, &mut second);
+        }
+        let titles = item_titles(client, &owner, &checklist).await;
+        assert_eq!(titles[0], "milk");
+        assert_eq!(titles.len(), 3);
+    }
+
+    #[rocket::async_test]
+    async fn items_cannot_move_next_to_items_in_other_checklists() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let groceries = create_checklist(client, &owner, "groceries").await;
+        let chores = create_checklist(client, &owner, "chores").await;
+        let milk = create_item(client, &owner, &groceries, "milk").await;
+        let dishes = create_item(client, &owner, &chores, "dishes").await;
+
+        let uri = format!("/api/v1/checklistitems/{}/move", milk);
+        let (status, _) = post_as(client, &owner, &uri, json!({ "before": dishes })).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn stream_asks_to_resync_when_the_client_falls_behind() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let checklist = create_checklist(client, &owner, "groceries").await;
+        let response = client
+            .get(format!("/api/v1/checklists/{}/stream", checklist))
+            .cookie(auth_cookie(client, &owner).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let broadcast = client.rocket().state::<ChecklistBroadcast>().unwrap();
+        let checklist_id = Uuid::parse_str(&checklist).unwrap();
+        for i in 0..2000 {
+            broadcast.publish(ChecklistEvent::new(
+                checklist_id,
+                ChecklistChange::Renamed {
+                    name: format!("name {}", i),
+                },
+            ));
+        }
+        let body = timeout(Duration::from_secs(5), response.into_string())
+            .await
+            .expect("The stream should end")
+            .unwrap();
+        assert!(body.contains(r#""type":"resync""#), "{}", body);
+    }
+}
@@ -0,0 +1,282 @@
+use rocket::{delete, get, http::Status, post, routes, serde, serde::json::Json, Route, State};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        auth::user_guard::AuthenticatedUser,
+        helpers::{convert_uuid, parse_uuid, ArgentApiResult, ArgentResult, NewData, OkData},
+    },
+    data::{
+        checklists::events::{ChecklistBroadcast, ChecklistChange, ChecklistEvent},
+        groups::{
+            models::{GroupMember, GroupMemberRequest, GroupRole, MemberGroup},
+            store::GroupStore,
+        },
+        users::models::{User, UserRole},
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+#[get("/groups")]
+async fn get_groups(
+    mut group_store: GroupStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<MemberGroup>> {
+    let groups = group_store.get_groups_for_user(user.get().id).await?;
+    ArgentApiResult::new(groups)
+}
+
+#[get("/groups/<id>/members")]
+async fn get_members(
+    mut group_store: GroupStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<GroupMember>> {
+    let group_id = convert_uuid(&id);
+    let user = user.get();
+    if user.role != UserRole::Admin && group_store.get_role(group_id, user.id).await?.is_none() {
+        return Err(ArgentError::forbidden());
+    }
+    let members = group_store.get_members(group_id).await?;
+    ArgentApiResult::new(members)
+}
+
+/// Admins add anyone, owners only people from another of their groups
+#[post("/groups/<id>/members", data = "<member_request>")]
+async fn set_member(
+    mut group_store: GroupStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    member_request: Json<GroupMemberRequest>,
+) -> ArgentApiResult<SimpleMessage> {
+    let group_id = convert_uuid(&id);
+    let user = user.get();
+    check_manager(&mut group_store, group_id, &user).await?;
+    let member = parse_uuid(&member_request.user_id, Status::BadRequest)?;
+    if user.role != UserRole::Admin && !group_store.shares_group(user.id, member).await? {
+        return Err(ArgentError::forbidden_msg(
+            "owners can only add people from their other groups",
+        ));
+    }
+    group_store
+        .set_member(group_id, member, member_request.role)
+        .await?;
+    ArgentApiResult::new_ok()
+}
+
+/// Owners and admins remove members, anyone may leave a group themselves
+#[delete("/groups/<id>/members/<user_id>")]
+async fn remove_member(
+    mut group_store: GroupStore,
+    id: serde::uuid::Uuid,
+    user_id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    events: &State<ChecklistBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let group_id = convert_uuid(&id);
+    let member = convert_uuid(&user_id);
+    let user = user.get();
+    if member != user.id {
+        check_manager(&mut group_store, group_id, &user).await?;
+    }
+    let revoked = group_store
+        .remove_member(group_id, member)
+        .await?
+        .ok_or_else(|| ArgentError::not_found_msg("user is not a member of the group"))?;
+    for share in revoked {
+        events.publish(ChecklistEvent::new(
+            share.checklist,
+            ChecklistChange::Unshared {
+                user: share.argent_user,
+            },
+        ));
+    }
+    ArgentApiResult::new_ok()
+}
+
+/// Admins manage every group, owners only their own
+async fn check_manager(
+    group_store: &mut GroupStore,
+    group_id: Uuid,
+    user: &User,
+) -> ArgentResult<()> {
+    if user.role == UserRole::Admin {
+        group_store.get_group(group_id).await?;
+        return Ok(());
+    }
+    match group_store.get_role(group_id, user.id).await? {
+        Some(GroupRole::Owner) => Ok(()),
+        _ => Err(ArgentError::forbidden()),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![get_groups, get_members, set_member, remove_member]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::Status,
+        serde::json::json,
+        tokio::time::{timeout, Duration},
+    };
+
+    use crate::{
+        data::users::models::UserRole,
+        testing::{
+            add_group, add_user, add_user_with_role, auth_cookie, create_checklist, delete_as,
+            get_as, post_as, share_checklist, TestApp,
+        },
+    };
+
+    #[rocket::async_test]
+    async fn owners_manage_membership() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let member = add_user(client, "member").await;
+        let newcomer = add_user(client, "newcomer").await;
+        let group = add_group(client, &[&owner, &member]).await;
+        add_group(client, &[&owner, &newcomer]).await;
+        let uri = format!("/api/v1/groups/{}/members", group);
+        let add_newcomer = json!({ "userId": newcomer.id.to_string(), "role": "Member" });
+
+        let (status, _) = post_as(client, &member, &uri, add_newcomer.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = get_as(client, &newcomer, &uri).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let (status, _) = post_as(client, &owner, &uri, add_newcomer).await;
+        assert_eq!(status, Status::Ok);
+        let (status, members) = get_as(client, &newcomer, &uri).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(members.as_array().unwrap().len(), 3);
+
+        let (status, _) = delete_as(client, &member, &format!("{}/{}", uri, newcomer.id)).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = delete_as(client, &owner, &format!("{}/{}", uri, newcomer.id)).await;
+        assert_eq!(status, Status::Ok);
+        let (_, users) = get_as(client, &member, "/api/v1/users").await;
+        assert_eq!(users.as_array().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn owners_only_add_people_from_their_other_groups() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let stranger = add_user(client, "stranger").await;
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let group = add_group(client, &[&owner]).await;
+        let uri = format!("/api/v1/groups/{}/members", group);
+        let add_stranger = json!({ "userId": stranger.id.to_string(), "role": "Member" });
+
+        let (status, _) = post_as(client, &owner, &uri, add_stranger.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = post_as(client, &admin, &uri, add_stranger).await;
+        assert_eq!(status, Status::Ok);
+        let (_, users) = get_as(client, &owner, "/api/v1/users").await;
+        assert_eq!(users.as_array().unwrap().len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn removing_a_member_revokes_their_shares() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let member = add_user(client, "member").await;
+        let group = add_group(client, &[&owner, &member]).await;
+        let checklist = create_checklist(client, &owner, "Groceries").await;
+        share_checklist(client, &owner, &checklist, &member, "Editor").await;
+        let (status, _) = post_as(
+            client,
+            &owner,
+            "/api/v1/wishlist/share",
+            json!({ "userId": member.id.to_string() }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let checklist_uri = format!("/api/v1/checklists/{}", checklist);
+        let wishlist_uri = format!("/api/v1/wishlists/{}/items", owner.id);
+        let (status, _) = get_as(client, &member, &checklist_uri).await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = get_as(client, &member, &wishlist_uri).await;
+        assert_eq!(status, Status::Ok);
+
+        let uri = format!("/api/v1/groups/{}/members/{}", group, member.id);
+        let (status, _) = delete_as(client, &owner, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let (status, _) = get_as(client, &member, &checklist_uri).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = get_as(client, &member, &wishlist_uri).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, checklists) = get_as(client, &owner, "/api/v1/checklists").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(checklists.as_array().unwrap().len(), 1);
+
+        // A share stays while another owner of the checklist still shares a group
+        let co_owner = add_user(client, "co-owner").await;
+        let friend = add_user(client, "friend").await;
+        let pair = add_group(client, &[&owner, &co_owner]).await;
+        add_group(client, &[&owner, &friend]).await;
+        let chores = create_checklist(client, &owner, "Chores").await;
+        share_checklist(client, &owner, &chores, &co_owner, "Owner").await;
+        share_checklist(client, &owner, &chores, &friend, "Editor").await;
+
+        let uri = format!("/api/v1/groups/{}/members/{}", pair, co_owner.id);
+        let (status, _) = delete_as(client, &co_owner, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let chores_uri = format!("/api/v1/checklists/{}", chores);
+        let (status, _) = get_as(client, &friend, &chores_uri).await;
+        assert_eq!(status, Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn removing_a_member_ends_their_checklist_streams() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let member = add_user(client, "member").await;
+        let group = add_group(client, &[&owner, &member]).await;
+        let checklist = create_checklist(client, &owner, "Groceries").await;
+        share_checklist(client, &owner, &checklist, &member, "Editor").await;
+        let response = client
+            .get(format!("/api/v1/checklists/{}/stream", checklist))
+            .cookie(auth_cookie(client, &member).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let uri = format!("/api/v1/groups/{}/members/{}", group, member.id);
+        let (status, _) = delete_as(client, &owner, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let body = timeout(Duration::from_secs(5), response.into_string())
+            .await
+            .expect("The stream should end")
+            .unwrap();
+        assert!(body.contains(r#""type":"unshared""#), "{}", body);
+        assert!(body.contains(&member.id.to_string()), "{}", body);
+    }
+
+    #[rocket::async_test]
+    async fn members_can_leave_but_the_last_owner_cannot() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let member = add_user(client, "member").await;
+        let group = add_group(client, &[&owner, &member]).await;
+        let uri = format!("/api/v1/groups/{}/members", group);
+
+        let (status, _) = delete_as(client, &owner, &format!("{}/{}", uri, owner.id)).await;
+        assert_eq!(status, Status::Conflict);
+        let demote = json!({ "userId": owner.id.to_string(), "role": "Member" });
+        let (status, _) = post_as(client, &owner, &uri, demote).await;
+        assert_eq!(status, Status::Conflict);
+
+        let (status, _) = delete_as(client, &member, &format!("{}/{}", uri, member.id)).await;
+        assert_eq!(status, Status::Ok);
+        let (_, groups) = get_as(client, &member, "/api/v1/groups").await;
+        assert!(groups.as_array().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+use rocket::{delete, get, http::Status, post, routes, serde, serde::json::Json, Route, State};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        auth::user_guard::AuthenticatedUser,
+        helpers::{convert_uuid, parse_uuid, ArgentApiResult, ArgentResult, NewData, OkData},
+    },
+    config::AuthenticationConfig,
+    data::{
+        groups::store::GroupStore,
+        invitations::{
+            models::{CreatedInvitation, Invitation, InvitationRequest, NewInvitation},
+            store::InvitationStore,
+        },
+        users::{
+            models::{User, UserRole},
+            store::UsersStore,
+        },
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+/// Admins manage all invitations, other users only their own and only if
+/// `users_can_invite` is configured. None means all invitations.
+fn invitations_managed_by(
+    user: &User,
+    auth_config: &AuthenticationConfig,
+) -> ArgentResult<Option<Uuid>> {
+    if user.role == UserRole::Admin {
+        Ok(None)
+    } else if auth_config.users_can_invite {
+        Ok(Some(user.id))
+    } else {
+        Err(ArgentError::forbidden())
+    }
+}
+
+/// The group the invited person joins: the requested one, which users other
+/// than admins must be in, or else the inviter's group if they are in one only
+async fn invitation_group(
+    group_store: &mut GroupStore,
+    user: &User,
+    requested: Option<&str>,
+) -> ArgentResult<Option<Uuid>> {
+    if let Some(group_id) = requested {
+        let group_id = parse_uuid(group_id, Status::BadRequest)?;
+        if user.role == UserRole::Admin {
+            group_store.get_group(group_id).await?;
+        } else if group_store.get_role(group_id, user.id).await?.is_none() {
+            return Err(ArgentError::forbidden_msg(
+                "you can only invite people to your own groups",
+            ));
+        }
+        return Ok(Some(group_id));
+    }
+    match group_store.get_groups_for_user(user.id).await?.as_slice() {
+        [] => Ok(None),
+        [group] => Ok(Some(group.id)),
+        _ => Err(ArgentError::bad_request_msg(
+            "groupId is required when you are in several groups",
+        )),
+    }
+}
+
+#[post("/invitations", data = "<invitation_request>")]
+async fn create_invitation(
+    mut invitation_store: InvitationStore,
+    mut users_store: UsersStore,
+    mut group_store: GroupStore,
+    user: AuthenticatedUser,
+    invitation_request: Json<InvitationRequest>,
+    auth_config: &State<AuthenticationConfig>,
+) -> ArgentApiResult<CreatedInvitation> {
+    let user = user.get();
+    let managed_by = invitations_managed_by(&user, auth_config)?;
+    let invitation_request = invitation_request.into_inner();
+    if managed_by.is_some() && invitation_request.role == UserRole::Admin {
+        return Err(ArgentError::forbidden_msg("only admins can invite admins"));
+    }
+    let group_id = invitation_group(
+        &mut group_store,
+        &user,
+        invitation_request.group_id.as_deref(),
+    )
+    .await?;
+    let invitation = NewInvitation::from_request(invitation_request, user.id, group_id)?;
+    if let Some(email) = &invitation.email {
+        if users_store.find_user_for_email(email).await?.is_some() {
+            return Err(ArgentError::new(
+                "a user with that email already exists",
+                Status::Conflict,
+            ));
+        }
+    }
+    invitation_store.add_invitation(&invitation).await?;
+    ArgentApiResult::new(CreatedInvitation::from_invitation(invitation))
+}
+
+#[get("/invitations")]
+async fn get_invitations(
+    mut invitation_store: InvitationStore,
+    user: AuthenticatedUser,
+    auth_config: &State<AuthenticationConfig>,
+) -> ArgentApiResult<Vec<Invitation>> {
+    let managed_by = invitations_managed_by(&user.get(), auth_config)?;
+    let invitations = invitation_store.get_pending_invitations(managed_by).await?;
+    ArgentApiResult::new(invitations)
+}
+
+#[delete("/invitations/<id>")]
+async fn delete_invitation(
+    mut invitation_store: InvitationStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    auth_config: &State<AuthenticationConfig>,
+) -> ArgentApiResult<SimpleMessage> {
+    let managed_by = invitations_managed_by(&user.get(), auth_config)?;
+    if !invitation_store
+        .delete_invitation(convert_uuid(&id), managed_by)
+        .await?
+    {
+        return Err(ArgentError::not_found());
+    }
+    ArgentApiResult::new_ok()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create_invitation, get_invitations, delete_invitation]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use rocket::{
+        http::{Header, Status},
+        local::asynchronous::Client,
+        serde::json::{json, Value},
+        tokio::{
+            join,
+            time::{sleep, Duration},
+        },
+    };
+    use sqlx::Acquire;
+    use uuid::Uuid;
+
+    use crate::{
+        config::AuthenticationConfig,
+        data::users::models::UserRole,
+        testing::{
+            add_group, add_user, add_user_with_role, connection, delete_as, get_as, google_token,
+            into_status_and_json, post_as, test_auth_config, unverified_google_token, TestApp,
+        },
+    };
+
+    async fn login(client: &Client, email: &str, invitation: Option<&str>) -> (Status, Value) {
+        let uri = match invitation {
+            Some(code) => format!("/api/v1/login?invitation={}", code),
+            None => String::from("/api/v1/login"),
+        };
+        let response = client
+            .get(uri)
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", google_token(email)),
+            ))
+            .dispatch()
+            .await;
+        into_status_and_json(response).await
+    }
+
+    #[rocket::async_test]
+    async fn uninvited_people_cannot_log_in() {
+        let app = TestApp::new().await;
+
+        let (status, _) = login(app.client(), "stranger@test.argent", None).await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = login(app.client(), "stranger@test.argent", Some("guess")).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn invited_email_gets_an_account_on_first_login() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+
+        let (status, invitation) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "email": "new.member@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(invitation["code"], Value::Null);
+        let (_, pending) = get_as(client, &admin, "/api/v1/invitations").await;
+        assert_eq!(pending.as_array().unwrap().len(), 1);
+
+        let (status, user) = login(client, "new.member@test.argent", None).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(user["name"], "new.member");
+        assert_eq!(user["role"], "User");
+
+        let (_, pending) = get_as(client, &admin, "/api/v1/invitations").await;
+        assert!(pending.as_array().unwrap().is_empty());
+        let (status, again) = login(client, "new.member@test.argent", None).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(again["id"], user["id"]);
+    }
+
+    #[rocket::async_test]
+    async fn unverified_emails_cannot_use_invitations() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let (status, _) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "email": "new.admin@test.argent", "role": "Admin" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+
+        let response = client
+            .get("/api/v1/login")
+            .header(Header::new(
+                "Authorization",
+                format!(
+                    "Bearer {}",
+                    unverified_google_token("new.admin@test.argent")
+                ),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let (_, pending) = get_as(client, &admin, "/api/v1/invitations").await;
+        assert_eq!(pending.as_array().unwrap().len(), 1);
+
+        let (status, user) = login(client, "new.admin@test.argent", None).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(user["role"], "Admin");
+    }
+
+    /// Holds a transaction that creates the account for `email`, and with
+    /// `invitation` uses it up, while `login` runs into it
+    async fn race_first_login(
+        client: &Client,
+        email: &str,
+        invitation: Option<Value>,
+        login: impl Future<Output = (Status, Value)>,
+    ) -> (Status, Value, Uuid) {
+        let mut conn = connection(client).await;
+        let mut tx = conn.begin().await.unwrap();
+        let id = Uuid::new_v4();
+        if let Some(invitation) = invitation {
+            sqlx::query("SELECT id FROM invitations WHERE id = $1 FOR UPDATE")
+                .bind(Uuid::parse_str(invitation["id"].as_str().unwrap()).unwrap())
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+            sqlx::query("UPDATE invitations SET accepted_at = now() WHERE id = $1")
+                .bind(Uuid::parse_str(invitation["id"].as_str().unwrap()).unwrap())
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO argent_users (id, name, email, role) VALUES ($1, 'racer', $2, 'User')",
+        )
+        .bind(id)
+        .bind(email)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+        let ((status, user), _) = join!(login, async {
+            // Long enough for the login to wait on the transaction
+            sleep(Duration::from_millis(500)).await;
+            tx.commit().await.unwrap();
+        });
+        (status, user, id)
+    }
+
+    #[rocket::async_test]
+    async fn first_login_finds_the_account_of_a_login_that_took_its_invitation() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let (status, invitation) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "email": "racer@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+
+        let (status, user, id) = race_first_login(
+            client,
+            "racer@test.argent",
+            Some(invitation),
+            login(client, "racer@test.argent", None),
+        )
+        .await;
+        assert_eq!(status, Status::Ok, "{}", user);
+        assert_eq!(user["id"], id.to_string());
+    }
+
+    #[rocket::async_test]
+    async fn first_login_finds_the_account_created_with_another_invitation() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let (status, invitation) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let code = invitation["code"].as_str().unwrap();
+
+        let (status, user, id) = race_first_login(
+            client,
+            "racer@test.argent",
+            None,
+            login(client, "racer@test.argent", Some(code)),
+        )
+        .await;
+        assert_eq!(status, Status::Ok, "{}", user);
+        assert_eq!(user["id"], id.to_string());
+        let (_, pending) = get_as(client, &admin, "/api/v1/invitations").await;
+        assert_eq!(pending.as_array().unwrap().len(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn invited_people_join_the_group_of_the_invitation() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+        let friend = add_user(client, "friend").await;
+        add_user(client, "outsider").await;
+        let group = add_group(client, &[&admin, &friend]).await;
+
+        let (status, invitation) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "email": "new.member@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(invitation["groupId"], group.to_string());
+        let (status, user) = login(client, "new.member@test.argent", None).await;
+        assert_eq!(status, Status::Ok);
+
+        let (_, users) = get_as(client, &friend, "/api/v1/users").await;
+        let mut names = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["admin", "friend", user["name"].as_str().unwrap()]);
+    }
+
+    #[rocket::async_test]
+    async fn users_only_invite_to_their_own_groups() {
+        let app = TestApp::with_auth_config(AuthenticationConfig {
+            users_can_invite: true,
+            ..test_auth_config()
+        })
+        .await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let other = add_user(client, "other").await;
+        let other_group = add_group(client, &[&other]).await;
+
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/invitations",
+            json!({ "role": "User", "groupId": other_group.to_string() }),
+        )
+        .await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn invitation_codes_are_single_use() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let admin = add_user_with_role(client, "admin", UserRole::Admin).await;
+
+        let (_, invitation) = post_as(
+            client,
+            &admin,
+            "/api/v1/invitations",
+            json!({ "role": "Admin" }),
+        )
+        .await;
+        let code = invitation["code"].as_str().unwrap();
+
+        let (status, user) = login(client, "first@test.argent", Some(code)).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(user["role"], "Admin");
+        let (status, _) = login(client, "second@test.argent", Some(code)).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn users_cannot_invite_unless_allowed() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/invitations",
+            json!({ "email": "friend@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn users_manage_their_own_invitations_when_allowed() {
+        let app = TestApp::with_auth_config(AuthenticationConfig {
+            users_can_invite: true,
+            ..test_auth_config()
+        })
+        .await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let other = add_user(client, "other").await;
+
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/invitations",
+            json!({ "email": "friend@test.argent", "role": "Admin" }),
+        )
+        .await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, invitation) = post_as(
+            client,
+            &user,
+            "/api/v1/invitations",
+            json!({ "email": "friend@test.argent", "role": "User" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let uri = format!("/api/v1/invitations/{}", invitation["id"].as_str().unwrap());
+
+        let (_, others) = get_as(client, &other, "/api/v1/invitations").await;
+        assert!(others.as_array().unwrap().is_empty());
+        let (status, _) = delete_as(client, &other, &uri).await;
+        assert_eq!(status, Status::NotFound);
+        let (status, _) = delete_as(client, &user, &uri).await;
+        assert_eq!(status, Status::Ok);
+    }
+}
@@ -0,0 +1,267 @@
+use rocket::{delete, get, http::CookieJar, routes, serde, Route, State};
+
+use crate::{
+    api::{
+        auth::{
+            cookie::{create_expired_cookie, create_expired_refresh_cookie},
+            user_guard::AuthenticatedUser,
+        },
+        helpers::{convert_uuid, ArgentApiResult, NewData, OkData},
+    },
+    config::AuthenticationConfig,
+    data::sessions::{
+        events::{SessionBroadcast, SessionRevoked},
+        models::Session,
+        store::SessionStore,
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+fn expire_cookies(cookies: &CookieJar<'_>, auth_config: &AuthenticationConfig) {
+    cookies.add(create_expired_cookie(auth_config));
+    cookies.add(create_expired_refresh_cookie(auth_config));
+}
+
+#[get("/sessions")]
+async fn get_sessions(
+    mut session_store: SessionStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<Session>> {
+    let current = user.session();
+    let sessions = session_store
+        .get_active_sessions(user.get().id)
+        .await?
+        .into_iter()
+        .map(|session| Session {
+            current: Some(session.id) == current,
+            ..session
+        })
+        .collect::<Vec<_>>();
+    ArgentApiResult::new(sessions)
+}
+
+#[delete("/sessions/<id>")]
+async fn revoke_session(
+    mut session_store: SessionStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+    cookies: &CookieJar<'_>,
+    auth_config: &State<AuthenticationConfig>,
+    sessions: &State<SessionBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let session = convert_uuid(&id);
+    let current = user.session();
+    if !session_store.revoke_session(session, user.get().id).await? {
+        return Err(ArgentError::not_found());
+    }
+    sessions.publish(SessionRevoked::Session(session));
+    if Some(session) == current {
+        expire_cookies(cookies, auth_config);
+    }
+    ArgentApiResult::new_ok()
+}
+
+/// Logs out everywhere, including this browser
+#[delete("/sessions")]
+async fn revoke_all_sessions(
+    mut session_store: SessionStore,
+    user: AuthenticatedUser,
+    cookies: &CookieJar<'_>,
+    auth_config: &State<AuthenticationConfig>,
+    sessions: &State<SessionBroadcast>,
+) -> ArgentApiResult<SimpleMessage> {
+    let user_id = user.get().id;
+    session_store.revoke_all_sessions(user_id).await?;
+    sessions.publish(SessionRevoked::AllOf(user_id));
+    expire_cookies(cookies, auth_config);
+    ArgentApiResult::new_ok()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![get_sessions, revoke_session, revoke_all_sessions]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{Cookie, Status},
+        local::asynchronous::Client,
+        tokio::time::{timeout, Duration},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        api::auth::jwt::decode_token,
+        testing::{
+            add_user, auth_cookie, create_checklist, into_status_and_json, origin,
+            test_auth_config, TestApp,
+        },
+    };
+
+    fn session_of(cookie: &Cookie<'_>) -> Uuid {
+        decode_token(cookie.value(), &test_auth_config())
+            .unwrap()
+            .session
+    }
+
+    async fn me_status(client: &Client, cookie: &Cookie<'static>) -> Status {
+        client
+            .get("/api/v1/me")
+            .cookie(cookie.clone())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[rocket::async_test]
+    async fn lists_active_sessions_and_marks_the_current_one() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let laptop = auth_cookie(client, &user).await;
+        let phone = auth_cookie(client, &user).await;
+        let other = add_user(client, "other").await;
+        auth_cookie(client, &other).await;
+
+        let response = client
+            .get("/api/v1/sessions")
+            .cookie(laptop.clone())
+            .header(origin())
+            .dispatch()
+            .await;
+        let (status, sessions) = into_status_and_json(response).await;
+        assert_eq!(status, Status::Ok);
+        let sessions = sessions.as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        let current = sessions
+            .iter()
+            .find(|session| session["current"] == true)
+            .unwrap();
+        assert_eq!(current["id"], session_of(&laptop).to_string());
+        assert_eq!(current["userAgent"], "argent-test");
+        assert!(sessions
+            .iter()
+            .any(|session| session["id"] == session_of(&phone).to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn revoked_session_cookies_are_rejected() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let laptop = auth_cookie(client, &user).await;
+        let phone = auth_cookie(client, &user).await;
+
+        let response = client
+            .delete(format!("/api/v1/sessions/{}", session_of(&phone)))
+            .cookie(laptop.clone())
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        assert_eq!(me_status(client, &phone).await, Status::Unauthorized);
+        assert_eq!(me_status(client, &laptop).await, Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn revoking_a_session_ends_its_streams() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let laptop = auth_cookie(client, &user).await;
+        let phone = auth_cookie(client, &user).await;
+        let checklist = create_checklist(client, &user, "groceries").await;
+        let chat = client
+            .get("/api/v1/chat/stream")
+            .cookie(phone.clone())
+            .dispatch()
+            .await;
+        assert_eq!(chat.status(), Status::Ok);
+        let checklist = client
+            .get(format!("/api/v1/checklists/{}/stream", checklist))
+            .cookie(phone.clone())
+            .dispatch()
+            .await;
+        assert_eq!(checklist.status(), Status::Ok);
+
+        let response = client
+            .delete(format!("/api/v1/sessions/{}", session_of(&phone)))
+            .cookie(laptop)
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        timeout(Duration::from_secs(5), chat.into_string())
+            .await
+            .expect("The chat stream should end");
+        timeout(Duration::from_secs(5), checklist.into_string())
+            .await
+            .expect("The checklist stream should end");
+    }
+
+    #[rocket::async_test]
+    async fn cannot_revoke_sessions_of_others() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let other = add_user(client, "other").await;
+        let cookie = auth_cookie(client, &user).await;
+        let other_cookie = auth_cookie(client, &other).await;
+
+        let response = client
+            .delete(format!("/api/v1/sessions/{}", session_of(&other_cookie)))
+            .cookie(cookie)
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(me_status(client, &other_cookie).await, Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn revoking_all_sessions_logs_out_everywhere() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let laptop = auth_cookie(client, &user).await;
+        let phone = auth_cookie(client, &user).await;
+
+        let response = client
+            .delete("/api/v1/sessions")
+            .cookie(laptop.clone())
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        assert_eq!(me_status(client, &laptop).await, Status::Unauthorized);
+        assert_eq!(me_status(client, &phone).await, Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn logging_out_everywhere_ends_streams() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let laptop = auth_cookie(client, &user).await;
+        let phone = auth_cookie(client, &user).await;
+        let chat = client
+            .get("/api/v1/chat/stream")
+            .cookie(phone)
+            .dispatch()
+            .await;
+        assert_eq!(chat.status(), Status::Ok);
+
+        let response = client
+            .delete("/api/v1/sessions")
+            .cookie(laptop)
+            .header(origin())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        timeout(Duration::from_secs(5), chat.into_string())
+            .await
+            .expect("The chat stream should end");
+    }
+}
@@ -0,0 +1,192 @@
+use rocket::{delete, get, post, routes, serde, serde::json::Json, Route};
+
+use crate::{
+    api::{
+        auth::user_guard::AuthenticatedUser,
+        helpers::{convert_uuid, ArgentApiResult, NewData, OkData},
+    },
+    data::api_tokens::{
+        models::{ApiToken, ApiTokenRequest, NewApiToken},
+        store::ApiTokenStore,
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+/// The response is the only time the token itself is shown
+#[post("/tokens", data = "<token_request>")]
+async fn create_token(
+    mut token_store: ApiTokenStore,
+    user: AuthenticatedUser,
+    token_request: Json<ApiTokenRequest>,
+) -> ArgentApiResult<NewApiToken> {
+    let token = NewApiToken::from_request(token_request.into_inner())?;
+    token_store.add_token(user.get().id, &token).await?;
+    ArgentApiResult::new(token)
+}
+
+#[get("/tokens")]
+async fn get_tokens(
+    mut token_store: ApiTokenStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<ApiToken>> {
+    let tokens = token_store.get_tokens(user.get().id).await?;
+    ArgentApiResult::new(tokens)
+}
+
+#[delete("/tokens/<id>")]
+async fn revoke_token(
+    mut token_store: ApiTokenStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    if !token_store
+        .delete_token(convert_uuid(&id), user.get().id)
+        .await?
+    {
+        return Err(ArgentError::not_found());
+    }
+    ArgentApiResult::new_ok()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create_token, get_tokens, revoke_token]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{Header, Status},
+        local::asynchronous::Client,
+        serde::json::{json, Value},
+    };
+
+    use crate::{
+        data::users::models::User,
+        testing::{
+            add_user, create_checklist, delete_as, get_as, into_status_and_json, post_as, TestApp,
+        },
+    };
+
+    async fn create_token(client: &Client, user: &User, scopes: Value) -> (String, String) {
+        let (status, token) = post_as(
+            client,
+            user,
+            "/api/v1/tokens",
+            json!({ "name": "home automation", "scopes": scopes }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        (
+            token["id"].as_str().unwrap().to_string(),
+            token["token"].as_str().unwrap().to_string(),
+        )
+    }
+
+    fn bearer(token: &str) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", token))
+    }
+
+    #[rocket::async_test]
+    async fn tokens_authenticate_within_their_scopes() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let checklist = create_checklist(client, &user, "groceries").await;
+        let (_, token) = create_token(
+            client,
+            &user,
+            json!(["checklists:read", "checklists:write"]),
+        )
+        .await;
+
+        let item = json!({ "title": "milk", "checklist": checklist });
+        let response = client
+            .post("/api/v1/checklistitems")
+            .header(bearer(&token))
+            .json(&item)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(format!("/api/v1/checklists/{}/items", checklist))
+            .header(bearer(&token))
+            .dispatch()
+            .await;
+        let (status, items) = into_status_and_json(response).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(items[0]["title"], "milk");
+
+        for uri in [
+            "/api/v1/wishlist/items",
+            "/api/v1/tokens",
+            "/api/v1/sessions",
+        ] {
+            let response = client.get(uri).header(bearer(&token)).dispatch().await;
+            assert_eq!(response.status(), Status::Forbidden, "GET {}", uri);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn revoked_and_unknown_tokens_are_rejected() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+        let (id, token) = create_token(client, &user, json!(["users:read"])).await;
+
+        let response = client
+            .get("/api/v1/me")
+            .header(bearer(&token))
+            .dispatch()
+            .await;
+        let (status, me) = into_status_and_json(response).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["id"], user.id.to_string());
+
+        let (_, tokens) = get_as(client, &user, "/api/v1/tokens").await;
+        let tokens = tokens.as_array().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0]["scopes"], json!(["users:read"]));
+        assert!(tokens[0]["lastUsed"].is_i64());
+        assert!(tokens[0].get("token").is_none());
+
+        let (status, _) = delete_as(client, &user, &format!("/api/v1/tokens/{}", id)).await;
+        assert_eq!(status, Status::Ok);
+        let response = client
+            .get("/api/v1/me")
+            .header(bearer(&token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get("/api/v1/me")
+            .header(Header::new("Authorization", "Basic dXNlcjpwYXNz"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn tokens_need_a_name_and_scopes() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let user = add_user(client, "user").await;
+
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/tokens",
+            json!({ "name": "script", "scopes": [] }),
+        )
+        .await;
+        assert_eq!(status, Status::BadRequest);
+        let (status, _) = post_as(
+            client,
+            &user,
+            "/api/v1/tokens",
+            json!({ "name": "script", "scopes": ["everything"] }),
+        )
+        .await;
+        assert_eq!(status, Status::UnprocessableEntity);
+    }
+}
@@ -1,35 +1,524 @@
-use rocket::{get, routes, Route};
+use std::io::Cursor;
+
+use rocket::{
+    data::ToByteUnit,
+    delete, get,
+    http::{ContentType, Header, Status},
+    patch, put,
+    response::{self, Responder},
+    routes,
+    serde::{json::Json, uuid::Uuid},
+    Data, Request, Response, Route,
+};
 
 use crate::{
     api::{
         auth::user_guard::AuthenticatedUser,
-        helpers::{ArgentApiResult, NewData},
+        helpers::{convert_uuid, ArgentApiResult, ArgentResult, IfNoneMatch, NewData, OkData},
     },
-    data::users::{
-        models::{User, UserForSharing},
-        store::UsersStore,
+    data::{
+        profiles::{
+            models::{avatar_content_type, Avatar, UserPreferences, UserPreferencesRequest},
+            store::ProfileStore,
+        },
+        users::{
+            models::{ProfileUpdateRequest, User, UserForSharing},
+            store::UsersStore,
+        },
     },
+    error::{ArgentError, SimpleMessage},
 };
 
+const MAX_AVATAR_KIB: u64 = 512;
+/// Avatar urls contain the version, so a versioned request can be cached forever
+const VERSIONED_AVATAR_CACHE_CONTROL: &str = "private, max-age=31536000, immutable";
+const AVATAR_CACHE_CONTROL: &str = "private, no-cache";
+
 #[get("/me")]
 fn me(user: AuthenticatedUser) -> ArgentApiResult<User> {
     ArgentApiResult::new(user.get())
 }
 
+#[patch("/me", data = "<profile_request>")]
+async fn update_me(
+    user: AuthenticatedUser,
+    mut users_store: UsersStore,
+    profile_request: Json<ProfileUpdateRequest>,
+) -> ArgentApiResult<User> {
+    let user_id = user.get().id;
+    let user = match profile_request.into_inner().name {
+        Some(name) if name.trim().is_empty() => {
+            return Err(ArgentError::bad_request_msg("name cannot be empty"))
+        }
+        Some(name) => users_store.update_name(user_id, &name).await?,
+        None => users_store.get_user(user_id).await?,
+    };
+    ArgentApiResult::new(user)
+}
+
+#[get("/me/preferences")]
+async fn get_preferences(
+    user: AuthenticatedUser,
+    mut profile_store: ProfileStore,
+) -> ArgentApiResult<UserPreferences> {
+    let preferences = profile_store.get_preferences(user.get().id).await?;
+    ArgentApiResult::new(preferences)
+}
+
+#[patch("/me/preferences", data = "<preferences_request>")]
+async fn update_preferences(
+    user: AuthenticatedUser,
+    mut profile_store: ProfileStore,
+    preferences_request: Json<UserPreferencesRequest>,
+) -> ArgentApiResult<UserPreferences> {
+    let user_id = user.get().id;
+    let preferences = profile_store
+        .get_preferences(user_id)
+        .await?
+        .update_from_request(preferences_request.into_inner())?;
+    profile_store
+        .save_preferences(user_id, &preferences)
+        .await?;
+    ArgentApiResult::new(preferences)
+}
+
+/// The body is the image itself
+#[put("/me/avatar", data = "<image>")]
+async fn upload_avatar(
+    user: AuthenticatedUser,
+    mut profile_store: ProfileStore,
+    content_type: Option<&ContentType>,
+    image: Data<'_>,
+) -> ArgentApiResult<User> {
+    let image = image
+        .open(MAX_AVATAR_KIB.kibibytes())
+        .into_bytes()
+        .await
+        .map_err(|e| ArgentError::Server(e.into()))?;
+    if !image.is_complete() {
+        return Err(ArgentError::new(
+            "avatar cannot be larger than 512 KiB",
+            Status::PayloadTooLarge,
+        ));
+    }
+    let content_type = avatar_content_type(content_type, &image)?;
+    let user = user.get();
+    profile_store
+        .set_avatar(user.id, &content_type.to_string(), &image)
+        .await?;
+    ArgentApiResult::new(user)
+}
+
+#[delete("/me/avatar")]
+async fn delete_avatar(
+    user: AuthenticatedUser,
+    mut profile_store: ProfileStore,
+) -> ArgentApiResult<SimpleMessage> {
+    if !profile_store.delete_avatar(user.get().id).await? {
+        return Err(ArgentError::not_found_msg("you have no avatar"));
+    }
+    ArgentApiResult::new_ok()
+}
+
+/// An avatar, or only its headers when the client already has this version
+struct AvatarResponse {
+    avatar: Avatar,
+    cache_control: &'static str,
+    not_modified: bool,
+}
+
+impl<'r> Responder<'r, 'static> for AvatarResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .raw_header("Cache-Control", self.cache_control)
+            .header(Header::new("ETag", avatar_etag(self.avatar.version)));
+        if self.not_modified {
+            response.status(Status::NotModified);
+        } else {
+            let content_type = ContentType::parse_flexible(&self.avatar.content_type)
+                .unwrap_or(ContentType::Binary);
+            response
+                .header(content_type)
+                .raw_header("X-Content-Type-Options", "nosniff")
+                .sized_body(self.avatar.image.len(), Cursor::new(self.avatar.image));
+        }
+        response.ok()
+    }
+}
+
+fn avatar_etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// `v` is the avatar version from `avatarUrl`. Only visible to the user
+/// and to the people they see in groups, checklists and wishlists.
+#[get("/users/<id>/avatar?<v>")]
+async fn get_avatar(
+    user: AuthenticatedUser,
+    mut profile_store: ProfileStore,
+    id: Uuid,
+    v: Option<i64>,
+    if_none_match: IfNoneMatch,
+) -> ArgentResult<AvatarResponse> {
+    let user_id = user.get().id;
+    let avatar_user = convert_uuid(&id);
+    if !profile_store.can_see_avatar(user_id, avatar_user).await? {
+        return Err(ArgentError::forbidden());
+    }
+    let avatar = profile_store.get_avatar(avatar_user).await?;
+    let cache_control = if v == Some(avatar.version) {
+        VERSIONED_AVATAR_CACHE_CONTROL
+    } else {
+        AVATAR_CACHE_CONTROL
+    };
+    let not_modified = if_none_match.0 == Some(avatar_etag(avatar.version));
+    Ok(AvatarResponse {
+        avatar,
+        cache_control,
+        not_modified,
+    })
+}
+
 #[get("/users")]
 async fn get_all_for_sharing(
-    _user: AuthenticatedUser,
+    user: AuthenticatedUser,
     mut users_store: UsersStore,
 ) -> ArgentApiResult<Vec<UserForSharing>> {
-    let users = users_store
-        .get_all_users()
-        .await?
-        .into_iter()
-        .map(UserForSharing::from_user)
-        .collect::<Vec<_>>();
+    let users = users_store.get_all_for_sharing(user.get().id).await?;
     ArgentApiResult::new(users)
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![me, get_all_for_sharing]
+    routes![
+        me,
+        update_me,
+        get_preferences,
+        update_preferences,
+        upload_avatar,
+        delete_avatar,
+        get_avatar,
+        get_all_for_sharing
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::Client,
+        serde::json::json,
+    };
+
+    use crate::{
+        data::users::models::User,
+        testing::{
+            add_group, add_user, auth_cookie, connection, create_checklist, delete_as, get_as,
+            origin, patch_as, share_checklist, TestApp,
+        },
+    };
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot really a png";
+
+    async fn upload_avatar(
+        client: &Client,
+        user: &User,
+        content_type: ContentType,
+        image: &[u8],
+    ) -> Status {
+        client
+            .put("/api/v1/me/avatar")
+            .cookie(auth_cookie(client, user).await)
+            .header(content_type)
+            .header(origin())
+            .body(image)
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[rocket::async_test]
+    async fn me_returns_the_authenticated_user() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let (status, me) = get_as(app.client(), &user, "/api/v1/me").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["id"], user.id.to_string());
+        assert_eq!(me["name"], "user");
+    }
+
+    #[rocket::async_test]
+    async fn me_requires_authentication() {
+        let app = TestApp::new().await;
+
+        let response = app.client().get("/api/v1/me").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn users_for_sharing_hide_emails() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let other = add_user(app.client(), "other").await;
+        add_group(app.client(), &[&user, &other]).await;
+
+        let (status, users) = get_as(app.client(), &user, "/api/v1/users").await;
+        assert_eq!(status, Status::Ok);
+        let users = users.as_array().unwrap();
+        assert_eq!(users.len(), 2);
+        let shared = users
+            .iter()
+            .find(|shared| shared["id"] == other.id.to_string())
+            .unwrap();
+        assert_eq!(shared["name"], "other");
+        assert!(shared.get("email").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn users_for_sharing_are_limited_to_group_members() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let housemate = add_user(app.client(), "housemate").await;
+        let friend = add_user(app.client(), "friend").await;
+        let stranger = add_user(app.client(), "stranger").await;
+        add_group(app.client(), &[&user, &housemate]).await;
+        add_group(app.client(), &[&friend, &user]).await;
+
+        let (_, users) = get_as(app.client(), &housemate, "/api/v1/users").await;
+        let mut names = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|shared| shared["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["housemate", "user"]);
+
+        let (_, users) = get_as(app.client(), &user, "/api/v1/users").await;
+        assert_eq!(users.as_array().unwrap().len(), 3);
+
+        let (_, users) = get_as(app.client(), &stranger, "/api/v1/users").await;
+        assert!(users.as_array().unwrap().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn users_can_change_their_display_name() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let (status, me) = patch_as(
+            app.client(),
+            &user,
+            "/api/v1/me",
+            json!({ "name": "New name", "role": "Admin" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["name"], "New name");
+
+        let (_, me) = get_as(app.client(), &user, "/api/v1/me").await;
+        assert_eq!(me["name"], "New name");
+        assert_eq!(me["role"], "User");
+
+        let (status, _) = patch_as(app.client(), &user, "/api/v1/me", json!({ "name": " " })).await;
+        assert_eq!(status, Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn changing_the_name_keeps_role_and_email_changed_elsewhere() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        // Caches the user as it is now
+        get_as(app.client(), &user, "/api/v1/me").await;
+
+        // Like an admin on another instance, whose change this cache does not see
+        let mut conn = connection(app.client()).await;
+        sqlx::query("UPDATE argent_users SET role = 'Admin', email = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind("changed@test.argent")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        let (status, me) = patch_as(
+            app.client(),
+            &user,
+            "/api/v1/me",
+            json!({ "name": "New name" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(me["name"], "New name");
+        assert_eq!(me["role"], "Admin");
+        assert_eq!(me["email"], "changed@test.argent");
+    }
+
+    #[rocket::async_test]
+    async fn preferences_start_as_defaults_and_keep_updates() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let (status, preferences) = get_as(app.client(), &user, "/api/v1/me/preferences").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(preferences["checklistSortOrder"], "manual");
+        assert_eq!(preferences["theme"], "system");
+        assert!(preferences["locale"].is_null());
+
+        let (status, _) = patch_as(
+            app.client(),
+            &user,
+            "/api/v1/me/preferences",
+            json!({ "theme": "dark", "locale": "nl-NL" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let (_, _) = patch_as(
+            app.client(),
+            &user,
+            "/api/v1/me/preferences",
+            json!({ "checklistSortOrder": "alphabetical" }),
+        )
+        .await;
+
+        let (_, preferences) = get_as(app.client(), &user, "/api/v1/me/preferences").await;
+        assert_eq!(preferences["checklistSortOrder"], "alphabetical");
+        assert_eq!(preferences["theme"], "dark");
+        assert_eq!(preferences["locale"], "nl-NL");
+
+        let (status, _) = patch_as(
+            app.client(),
+            &user,
+            "/api/v1/me/preferences",
+            json!({ "colour": "blue" }),
+        )
+        .await;
+        assert_eq!(status, Status::UnprocessableEntity);
+    }
+
+    #[rocket::async_test]
+    async fn avatars_are_shared_and_cached() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+        let other = add_user(app.client(), "other").await;
+        add_group(app.client(), &[&user, &other]).await;
+
+        let status = upload_avatar(app.client(), &user, ContentType::PNG, PNG).await;
+        assert_eq!(status, Status::Ok);
+
+        let (_, users) = get_as(app.client(), &other, "/api/v1/users").await;
+        let shared = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|shared| shared["id"] == user.id.to_string())
+            .unwrap();
+        let avatar_url = shared["avatarUrl"].as_str().unwrap().to_string();
+        assert!(avatar_url.starts_with(&format!("/api/v1/users/{}/avatar?v=", user.id)));
+
+        let response = app
+            .client()
+            .get(avatar_url.clone())
+            .cookie(auth_cookie(app.client(), &other).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert!(response
+            .headers()
+            .get_one("Cache-Control")
+            .unwrap()
+            .contains("immutable"));
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(response.into_bytes().await.unwrap(), PNG);
+
+        let stranger = add_user(app.client(), "stranger").await;
+        let response = app
+            .client()
+            .get(avatar_url.clone())
+            .cookie(auth_cookie(app.client(), &stranger).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = app
+            .client()
+            .get(avatar_url.clone())
+            .cookie(auth_cookie(app.client(), &user).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = app
+            .client()
+            .get(format!("/api/v1/users/{}/avatar", user.id))
+            .cookie(auth_cookie(app.client(), &other).await)
+            .header(Header::new("If-None-Match", etag))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("private, no-cache")
+        );
+
+        let (status, _) = delete_as(app.client(), &user, "/api/v1/me/avatar").await;
+        assert_eq!(status, Status::Ok);
+        let (_, users) = get_as(app.client(), &other, "/api/v1/users").await;
+        assert!(users
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|shared| shared["avatarUrl"].is_null()));
+    }
+
+    #[rocket::async_test]
+    async fn avatars_are_visible_on_checklists_shared_across_groups() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let co_owner = add_user(client, "co-owner").await;
+        let friend = add_user(client, "friend").await;
+        add_group(client, &[&owner, &co_owner]).await;
+        add_group(client, &[&owner, &friend]).await;
+        let chores = create_checklist(client, &owner, "Chores").await;
+        share_checklist(client, &owner, &chores, &co_owner, "Owner").await;
+        share_checklist(client, &owner, &chores, &friend, "Editor").await;
+        let status = upload_avatar(client, &co_owner, ContentType::PNG, PNG).await;
+        assert_eq!(status, Status::Ok);
+
+        let uri = format!("/api/v1/checklists/{}/users", chores);
+        let (status, users) = get_as(client, &friend, &uri).await;
+        assert_eq!(status, Status::Ok);
+        let avatar_url = users
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|shared| shared["id"] == co_owner.id.to_string())
+            .unwrap()["avatarUrl"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = client
+            .get(avatar_url)
+            .cookie(auth_cookie(client, &friend).await)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn avatars_must_be_small_images() {
+        let app = TestApp::new().await;
+        let user = add_user(app.client(), "user").await;
+
+        let status = upload_avatar(app.client(), &user, ContentType::HTML, b"<html></html>").await;
+        assert_eq!(status, Status::BadRequest);
+        let status = upload_avatar(app.client(), &user, ContentType::JPEG, PNG).await;
+        assert_eq!(status, Status::BadRequest);
+
+        let mut large = PNG.to_vec();
+        large.resize(600 * 1024, 0);
+        let status = upload_avatar(app.client(), &user, ContentType::PNG, &large).await;
+        assert_eq!(status, Status::PayloadTooLarge);
+    }
 }
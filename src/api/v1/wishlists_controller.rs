@@ -0,0 +1,349 @@
+use rocket::{delete, get, http::Status, patch, post, routes, serde, serde::json::Json, Route};
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        auth::user_guard::AuthenticatedUser,
+        helpers::{convert_uuid, parse_uuid, ArgentApiResult, ArgentResult, NewData, OkData},
+    },
+    data::{
+        groups::store::GroupStore,
+        users::models::{User, UserForSharing},
+        wishlists::{
+            models::{
+                OwnWishlistItem, SharedWishlistItem, WishlistItem, WishlistItemRequest,
+                WishlistShareRequest,
+            },
+            store::WishlistStore,
+        },
+    },
+    error::{ArgentError, SimpleMessage},
+};
+
+#[get("/wishlists")]
+async fn get_wishlists(
+    mut wishlist_store: WishlistStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<UserForSharing>> {
+    let wishlists = wishlist_store.get_wishlists_for_user(user.get().id).await?;
+    ArgentApiResult::new(wishlists)
+}
+
+#[get("/wishlist/items")]
+async fn get_own_wishlist_items(
+    mut wishlist_store: WishlistStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<OwnWishlistItem>> {
+    let items = wishlist_store
+        .get_wishlist_items(user.get().id)
+        .await?
+        .into_iter()
+        .map(OwnWishlistItem::from_item)
+        .collect::<Vec<_>>();
+    ArgentApiResult::new(items)
+}
+
+#[get("/wishlists/<user_id>/items")]
+async fn get_wishlist_items(
+    mut wishlist_store: WishlistStore,
+    user_id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<SharedWishlistItem>> {
+    let wishlist_user = convert_uuid(&user_id);
+    let user = user.get();
+    check_viewer_access(&mut wishlist_store, wishlist_user, &user).await?;
+    let items = wishlist_store
+        .get_wishlist_items(wishlist_user)
+        .await?
+        .into_iter()
+        .map(|item| SharedWishlistItem::for_viewer(item, user.id))
+        .collect::<Vec<_>>();
+    ArgentApiResult::new(items)
+}
+
+#[post("/wishlistitems", data = "<item_request>")]
+async fn create_wishlist_item(
+    mut wishlist_store: WishlistStore,
+    item_request: Json<WishlistItemRequest>,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let item = WishlistItem::from_request(item_request.into_inner(), user.get().id);
+    wishlist_store.add_item(item).await?;
+    ArgentApiResult::new_ok()
+}
+
+#[patch("/wishlistitems/<id>", data = "<item_request>")]
+async fn update_wishlist_item(
+    mut wishlist_store: WishlistStore,
+    id: serde::uuid::Uuid,
+    item_request: Json<WishlistItemRequest>,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let item = get_own_item(&mut wishlist_store, convert_uuid(&id), user.get()).await?;
+    let item = item.update_from_request(item_request.into_inner());
+    wishlist_store.update_item(item).await?;
+    ArgentApiResult::new_ok()
+}
+
+#[delete("/wishlistitems/<id>")]
+async fn delete_wishlist_item(
+    mut wishlist_store: WishlistStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let item = get_own_item(&mut wishlist_store, convert_uuid(&id), user.get()).await?;
+    wishlist_store.delete_item(item.id).await?;
+    ArgentApiResult::new_ok()
+}
+
+#[post("/wishlistitems/<id>/claim")]
+async fn claim_wishlist_item(
+    mut wishlist_store: WishlistStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let user = user.get();
+    let item = wishlist_store.get_item(convert_uuid(&id)).await?;
+    check_viewer_access(&mut wishlist_store, item.argent_user, &user).await?;
+    if !wishlist_store.claim_item(item.id, user.id).await? {
+        return Err(ArgentError::new("item is already taken", Status::Conflict));
+    }
+    ArgentApiResult::new_ok()
+}
+
+#[post("/wishlistitems/<id>/unclaim")]
+async fn unclaim_wishlist_item(
+    mut wishlist_store: WishlistStore,
+    id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    let user = user.get();
+    let item = wishlist_store.get_item(convert_uuid(&id)).await?;
+    check_viewer_access(&mut wishlist_store, item.argent_user, &user).await?;
+    if !wishlist_store.unclaim_item(item.id, user.id).await? {
+        return Err(ArgentError::bad_request_msg("item is not taken by you"));
+    }
+    ArgentApiResult::new_ok()
+}
+
+#[get("/wishlist/users")]
+async fn get_users_for_wishlist(
+    mut wishlist_store: WishlistStore,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<Vec<UserForSharing>> {
+    let users = wishlist_store.get_users_with_access(user.get().id).await?;
+    ArgentApiResult::new(users)
+}
+
+#[post("/wishlist/share", data = "<share_req>")]
+async fn share(
+    mut wishlist_store: WishlistStore,
+    mut group_store: GroupStore,
+    user: AuthenticatedUser,
+    share_req: Json<WishlistShareRequest>,
+) -> ArgentApiResult<SimpleMessage> {
+    let user = user.get();
+    let access_user = parse_uuid(&share_req.user_id, Status::BadRequest)?;
+    if access_user == user.id {
+        return Err(ArgentError::bad_request_msg(
+            "cannot share a wishlist with its owner",
+        ));
+    }
+    if !group_store.shares_group(user.id, access_user).await? {
+        return Err(ArgentError::forbidden_msg(
+            "wishlists can only be shared with members of your groups",
+        ));
+    }
+    wishlist_store.add_access(user.id, access_user).await?;
+    ArgentApiResult::new_ok()
+}
+
+#[post("/wishlist/unshare/<user_id>")]
+async fn un_share(
+    mut wishlist_store: WishlistStore,
+    user_id: serde::uuid::Uuid,
+    user: AuthenticatedUser,
+) -> ArgentApiResult<SimpleMessage> {
+    wishlist_store
+        .remove_access(user.get().id, convert_uuid(&user_id))
+        .await?;
+    ArgentApiResult::new_ok()
+}
+
+/// Only people the wishlist is shared with may view it through the shared
+/// models, the owner uses the own wishlist routes so that claims stay hidden.
+async fn check_viewer_access(
+    wishlist_store: &mut WishlistStore,
+    wishlist_user: Uuid,
+    user: &User,
+) -> ArgentResult<()> {
+    if wishlist_user != user.id && wishlist_store.has_access(wishlist_user, user.id).await? {
+        Ok(())
+    } else {
+        Err(ArgentError::forbidden())
+    }
+}
+
+async fn get_own_item(
+    wishlist_store: &mut WishlistStore,
+    item_id: Uuid,
+    user: User,
+) -> ArgentResult<WishlistItem> {
+    let item = wishlist_store.get_item(item_id).await?;
+    if item.argent_user == user.id {
+        Ok(item)
+    } else {
+        Err(ArgentError::forbidden())
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_wishlists,
+        get_own_wishlist_items,
+        get_wishlist_items,
+        create_wishlist_item,
+        update_wishlist_item,
+        delete_wishlist_item,
+        claim_wishlist_item,
+        unclaim_wishlist_item,
+        get_users_for_wishlist,
+        share,
+        un_share
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{http::Status, local::asynchronous::Client, serde::json::json};
+
+    use crate::{
+        data::users::models::User,
+        testing::{add_group, add_user, get_as, post_as, TestApp},
+    };
+
+    /// Adds a wish and returns its id
+    async fn add_wish(client: &Client, owner: &User, title: &str) -> String {
+        let (status, _) = post_as(
+            client,
+            owner,
+            "/api/v1/wishlistitems",
+            json!({ "title": title, "description": "" }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok);
+        let (_, items) = get_as(client, owner, "/api/v1/wishlist/items").await;
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|item| item["title"] == title)
+            .map(|item| item["id"].as_str().unwrap().to_string())
+            .unwrap()
+    }
+
+    async fn share_wishlist(client: &Client, owner: &User, with: &User) -> Status {
+        let (status, _) = post_as(
+            client,
+            owner,
+            "/api/v1/wishlist/share",
+            json!({ "userId": with.id.to_string() }),
+        )
+        .await;
+        status
+    }
+
+    async fn claim(client: &Client, user: &User, item: &str, action: &str) -> Status {
+        let uri = format!("/api/v1/wishlistitems/{}/{}", item, action);
+        post_as(client, user, &uri, json!({})).await.0
+    }
+
+    #[rocket::async_test]
+    async fn claims_are_hidden_from_the_owner() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let claimer = add_user(client, "claimer").await;
+        let viewer = add_user(client, "viewer").await;
+        add_group(client, &[&owner, &claimer, &viewer]).await;
+        assert_eq!(share_wishlist(client, &owner, &claimer).await, Status::Ok);
+        assert_eq!(share_wishlist(client, &owner, &viewer).await, Status::Ok);
+        let wish = add_wish(client, &owner, "bike").await;
+
+        assert_eq!(claim(client, &claimer, &wish, "claim").await, Status::Ok);
+
+        let (_, own_items) = get_as(client, &owner, "/api/v1/wishlist/items").await;
+        let own_item = own_items[0].as_object().unwrap();
+        assert!(!own_item.contains_key("taken"));
+        assert!(!own_item.contains_key("takenBy"));
+        assert!(!own_item.contains_key("takenByMe"));
+
+        let uri = format!("/api/v1/wishlists/{}/items", owner.id);
+        let (_, items) = get_as(client, &viewer, &uri).await;
+        assert_eq!(items[0]["taken"], true);
+        assert_eq!(items[0]["takenByMe"], false);
+        let (_, items) = get_as(client, &claimer, &uri).await;
+        assert_eq!(items[0]["takenByMe"], true);
+    }
+
+    #[rocket::async_test]
+    async fn only_one_viewer_can_claim_and_only_they_can_unclaim() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let claimer = add_user(client, "claimer").await;
+        let other = add_user(client, "other").await;
+        add_group(client, &[&owner, &claimer, &other]).await;
+        assert_eq!(share_wishlist(client, &owner, &claimer).await, Status::Ok);
+        assert_eq!(share_wishlist(client, &owner, &other).await, Status::Ok);
+        let wish = add_wish(client, &owner, "bike").await;
+
+        assert_eq!(claim(client, &claimer, &wish, "claim").await, Status::Ok);
+        assert_eq!(
+            claim(client, &other, &wish, "claim").await,
+            Status::Conflict
+        );
+        assert_ne!(claim(client, &other, &wish, "unclaim").await, Status::Ok);
+        assert_eq!(claim(client, &claimer, &wish, "unclaim").await, Status::Ok);
+        assert_eq!(claim(client, &other, &wish, "claim").await, Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn wishlists_need_access() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let stranger = add_user(client, "stranger").await;
+        add_group(client, &[&owner, &stranger]).await;
+        let wish = add_wish(client, &owner, "bike").await;
+
+        let uri = format!("/api/v1/wishlists/{}/items", owner.id);
+        let (status, _) = get_as(client, &stranger, &uri).await;
+        assert_eq!(status, Status::Forbidden);
+        assert_eq!(
+            claim(client, &stranger, &wish, "claim").await,
+            Status::Forbidden
+        );
+        // The owner only sees their wishlist through the own routes
+        let (status, _) = get_as(client, &owner, &uri).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn wishlists_are_only_shared_within_groups() {
+        let app = TestApp::new().await;
+        let client = app.client();
+        let owner = add_user(client, "owner").await;
+        let member = add_user(client, "member").await;
+        let outsider = add_user(client, "outsider").await;
+        add_group(client, &[&owner, &member]).await;
+
+        assert_eq!(
+            share_wishlist(client, &owner, &outsider).await,
+            Status::Forbidden
+        );
+        assert_eq!(share_wishlist(client, &owner, &member).await, Status::Ok);
+        let (_, users) = get_as(client, &owner, "/api/v1/wishlist/users").await;
+        assert_eq!(users.as_array().unwrap().len(), 1);
+    }
+}
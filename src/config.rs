@@ -1,20 +1,435 @@
-use rocket::serde::json::serde_json;
+use std::fmt;
+
+use jsonwebtoken::Algorithm;
+use rocket::{
+    figment::{
+        providers::{Env, Serialized},
+        Figment,
+    },
+    serde::json::serde_json::{self, Value},
+};
 use serde::Deserialize;
 
+use crate::api::auth::keyring::Keyring;
+
+/// Keys without a default, `auth.jwt_key` or `auth.signing_keys` is checked separately
+const REQUIRED_KEYS: [&str; 3] = ["auth.secure_cookie", "auth.cookie_name", "idp.audience"];
+/// Sections that used to be set as JSON in `ARGENT_AUTH` and `ARGENT_IDP`
+const LEGACY_JSON_SECTIONS: [&str; 2] = ["auth", "idp"];
+
+/// Everything Argent reads at startup, from the selected profile of
+/// `Rocket.toml` and from `ARGENT_` environment variables
+#[derive(Debug)]
+pub struct ArgentConfig {
+    pub auth: AuthenticationConfig,
+    pub idp: IdentityProviderConfig,
+    pub dev: DevConfig,
+}
+
+/// Only used by debug builds
+#[derive(Debug, Default, Deserialize)]
+pub struct DevConfig {
+    /// TOML file with users and checklists added at startup, see `dev-seed.toml`
+    #[serde(default)]
+    pub seed_file: Option<String>,
+}
+
+/// Every missing or invalid key, so they can all be fixed in one go
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The variable that sets `key`, `auth.cookie_name` is set by `ARGENT_AUTH__COOKIE_NAME`
+fn env_name(key: &str) -> String {
+    format!("ARGENT_{}", key.replace('.', "__").to_uppercase())
+}
+
+/// `jwtKey` becomes `jwt_key`, in nested objects too
+fn snake_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = key.chars().fold(String::new(), |mut key, c| {
+                        if c.is_ascii_uppercase() {
+                            key.push('_');
+                        }
+                        key.push(c.to_ascii_lowercase());
+                        key
+                    });
+                    (key, snake_case_keys(value))
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(snake_case_keys).collect()),
+        value => value,
+    }
+}
+
+impl ArgentConfig {
+    /// Rocket's own configuration with `ARGENT_` variables on top. A variable
+    /// can hold a whole section, `ARGENT_AUTH={cookie_name="argent", ...}`,
+    /// or one key of it, `ARGENT_AUTH__COOKIE_NAME=argent`.
+    ///
+    /// `ARGENT_AUTH` and `ARGENT_IDP` holding JSON with camelCase keys, the way
+    /// they were set before, still work.
+    pub fn figment() -> Figment {
+        let legacy = LEGACY_JSON_SECTIONS
+            .into_iter()
+            .filter_map(|section| {
+                let value = std::env::var(env_name(section)).ok()?;
+                match serde_json::from_str::<Value>(&value) {
+                    Ok(json @ Value::Object(_)) => Some((section, snake_case_keys(json))),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let legacy_sections = legacy
+            .iter()
+            .map(|(section, _)| *section)
+            .collect::<Vec<_>>();
+        let figment = rocket::Config::figment().merge(
+            Env::prefixed("ARGENT_")
+                .split("__")
+                .ignore(&legacy_sections)
+                .global(),
+        );
+        legacy
+            .into_iter()
+            .fold(figment, |figment, (section, json)| {
+                figment.merge(Serialized::global(section, json))
+            })
+    }
+
+    pub fn from_figment(figment: &Figment) -> Result<ArgentConfig, ConfigErrors> {
+        let mut errors: Vec<String> = REQUIRED_KEYS
+            .into_iter()
+            .filter(|key| figment.find_value(key).is_err())
+            .map(|key| {
+                format!(
+                    "{} is missing, set it in Rocket.toml or as {}",
+                    key,
+                    env_name(key)
+                )
+            })
+            .collect();
+        if figment.find_value("auth.jwt_key").is_err()
+            && figment.find_value("auth.signing_keys").is_err()
+        {
+            errors.push(format!(
+                "auth.jwt_key or auth.signing_keys is missing, set one in Rocket.toml or as {} or {}",
+                env_name("auth.jwt_key"),
+                env_name("auth.signing_keys")
+            ));
+        }
+        if !errors.is_empty() {
+            return Err(ConfigErrors(errors));
+        }
+
+        let auth = figment
+            .extract_inner::<AuthenticationConfig>("auth")
+            .map_err(|e| e.into_iter().map(|e| e.to_string()).collect::<Vec<_>>())
+            .and_then(|auth| auth.validate().map_err(|e| vec![e]));
+        let idp = figment
+            .extract_inner::<IdentityProviderConfig>("idp")
+            .map_err(|e| e.into_iter().map(|e| e.to_string()).collect::<Vec<_>>())
+            .and_then(|idp| idp.validate().map(|_| idp).map_err(|e| vec![e]));
+        let dev = match figment.find_value("dev") {
+            Ok(_) => figment
+                .extract_inner::<DevConfig>("dev")
+                .map_err(|e| e.into_iter().map(|e| e.to_string()).collect::<Vec<_>>()),
+            Err(_) => Ok(DevConfig::default()),
+        };
+        match (auth, idp, dev) {
+            (Ok(auth), Ok(idp), Ok(dev)) => Ok(ArgentConfig { auth, idp, dev }),
+            (auth, idp, dev) => Err(ConfigErrors(
+                [auth.err(), idp.err(), dev.err()]
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .collect(),
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
 pub struct AuthenticationConfig {
-    pub jwt_key: String,
+    /// A single HS256 key. Only verifies older tokens once `signing_keys` are configured.
+    #[serde(default)]
+    pub jwt_key: Option<String>,
+    /// Exactly one of these is not retired and signs new session tokens
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKeyConfig>,
     pub secure_cookie: bool,
     pub cookie_name: String,
+    /// Origins of frontends served from elsewhere, they get CORS access and may
+    /// make state-changing requests with the session cookie
+    #[serde(default)]
+    pub allowed_origins: AllowedOrigins,
+    /// Whether users other than admins may invite new users
+    #[serde(default)]
+    pub users_can_invite: bool,
+    #[serde(skip)]
+    pub keyring: Keyring,
+}
+
+/// A key for session tokens. HS256 keys have a `secret`, ES256 and EdDSA keys
+/// PEM files, where retired keys only need the public key.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKeyConfig {
+    pub kid: String,
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Algorithm,
+    pub secret: Option<String>,
+    pub private_key_file: Option<String>,
+    pub public_key_file: Option<String>,
+    /// Retired keys verify tokens signed before a rotation but sign nothing new
+    #[serde(default)]
+    pub retired: bool,
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+impl AuthenticationConfig {
+    /// Also loads the keyring, which reads the PEM files of the keys
+    fn validate(self) -> Result<Self, String> {
+        if self.cookie_name.trim().is_empty() {
+            return Err(String::from("auth.cookie_name cannot be empty"));
+        }
+        self.load_keys()
+            .map_err(|e| format!("auth.signing_keys: {}", e))
+    }
+
+    pub fn load_keys(self) -> Result<Self, String> {
+        let keyring = Keyring::load(self.jwt_key.as_deref(), &self.signing_keys)?;
+        Ok(AuthenticationConfig { keyring, ..self })
+    }
 }
 
 impl AuthenticationConfig {
-    pub fn from_env() -> Self {
-        let as_string = std::env::var("ARGENT_AUTH").unwrap();
-        serde_json::from_str(&as_string).unwrap()
+    pub fn refresh_cookie_name(&self) -> String {
+        format!("{}-refresh", self.cookie_name)
     }
 }
 
 unsafe impl Send for AuthenticationConfig {}
 unsafe impl Sync for AuthenticationConfig {}
+
+/// Origins like `https://argent.example.com`, or `https://*.preview.example.com`
+/// for any subdomain
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct AllowedOrigins(Vec<String>);
+
+impl TryFrom<Vec<String>> for AllowedOrigins {
+    type Error = String;
+
+    fn try_from(origins: Vec<String>) -> Result<Self, Self::Error> {
+        let origins = origins
+            .into_iter()
+            .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+            .collect::<Vec<_>>();
+        for origin in &origins {
+            let host = origin
+                .split_once("://")
+                .map(|(_, host)| host)
+                .ok_or_else(|| format!("allowed origin {} has no scheme", origin))?;
+            let wildcard_ok = host.strip_prefix("*.").unwrap_or(host);
+            if wildcard_ok.is_empty() || wildcard_ok.contains(['*', '/']) {
+                return Err(format!("allowed origin {} is not valid", origin));
+            }
+        }
+        Ok(AllowedOrigins(origins))
+    }
+}
+
+impl AllowedOrigins {
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+        self.0
+            .iter()
+            .any(|allowed| match allowed.split_once("://*.") {
+                Some((scheme, domain)) => origin
+                    .strip_prefix(scheme)
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|host| host.strip_suffix(domain))
+                    .and_then(|subdomain| subdomain.strip_suffix('.'))
+                    .is_some_and(|subdomain| {
+                        !subdomain.is_empty()
+                            && subdomain
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                    }),
+                None => *allowed == origin,
+            })
+    }
+}
+
+const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+const GOOGLE_ISSUERS: [&str; 2] = ["https://accounts.google.com", "accounts.google.com"];
+
+/// The OpenID provider whose ID tokens are accepted at login, Google unless
+/// pointed somewhere else. `audience` is our OAuth client id.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentityProviderConfig {
+    #[serde(default = "google_jwks_url")]
+    pub jwks_url: String,
+    #[serde(default = "google_issuers")]
+    pub issuers: Vec<String>,
+    pub audience: String,
+}
+
+fn google_jwks_url() -> String {
+    String::from(GOOGLE_JWKS_URL)
+}
+
+fn google_issuers() -> Vec<String> {
+    GOOGLE_ISSUERS
+        .iter()
+        .map(|issuer| issuer.to_string())
+        .collect()
+}
+
+impl IdentityProviderConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.audience.trim().is_empty() {
+            return Err(String::from("idp.audience cannot be empty"));
+        }
+        if !self.jwks_url.starts_with("https://") && !self.jwks_url.starts_with("http://") {
+            return Err(format!("idp.jwks_url {} is not a url", self.jwks_url));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::{
+        providers::{Format, Toml},
+        Figment, Jail,
+    };
+
+    use super::{AllowedOrigins, ArgentConfig};
+
+    fn config(toml: &str) -> Result<ArgentConfig, Vec<String>> {
+        ArgentConfig::from_figment(&Figment::new().merge(Toml::string(toml))).map_err(|e| e.0)
+    }
+
+    fn origins(origins: &[&str]) -> Result<AllowedOrigins, String> {
+        AllowedOrigins::try_from(origins.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn allowed_origins_match_exactly_or_by_subdomain() {
+        let allowed = origins(&[
+            "https://Argent.example.com/",
+            "https://*.preview.example.com",
+        ])
+        .unwrap();
+        assert!(allowed.matches("https://argent.example.com"));
+        assert!(allowed.matches("https://pr-12.preview.example.com"));
+        assert!(allowed.matches("https://a.b.preview.example.com"));
+        assert!(!allowed.matches("http://argent.example.com"));
+        assert!(!allowed.matches("https://preview.example.com"));
+        assert!(!allowed.matches("https://evilpreview.example.com"));
+        assert!(!allowed.matches("https://x.preview.example.com.evil.com"));
+        assert!(!allowed.matches("http://pr-12.preview.example.com"));
+    }
+
+    #[test]
+    fn allowed_origins_need_a_scheme_and_a_host() {
+        assert!(origins(&["argent.example.com"]).is_err());
+        assert!(origins(&["*"]).is_err());
+        assert!(origins(&["https://*"]).is_err());
+        assert!(origins(&["https://*.*.example.com"]).is_err());
+    }
+
+    #[test]
+    fn config_is_read_from_sections() {
+        let config = config(
+            r#"
+            [auth]
+            jwt_key = "secret"
+            secure_cookie = true
+            cookie_name = "argent"
+            allowed_origins = ["https://argent.example.com"]
+
+            [idp]
+            audience = "client-id"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.auth.cookie_name, "argent");
+        assert!(config.auth.secure_cookie);
+        assert!(config
+            .auth
+            .allowed_origins
+            .matches("https://argent.example.com"));
+        assert_eq!(config.idp.audience, "client-id");
+        assert_eq!(config.idp.jwks_url, super::GOOGLE_JWKS_URL);
+    }
+
+    #[test]
+    fn every_missing_key_is_reported() {
+        let errors = config("[auth]\nsecure_cookie = false").unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].contains("ARGENT_AUTH__COOKIE_NAME"));
+        assert!(errors[1].contains("ARGENT_IDP__AUDIENCE"));
+        assert!(errors[2].contains("auth.jwt_key or auth.signing_keys"));
+    }
+
+    #[test]
+    fn invalid_values_are_reported_for_both_sections() {
+        let errors = config(
+            r#"
+            [auth]
+            jwt_key = "secret"
+            secure_cookie = "sometimes"
+            cookie_name = "argent"
+
+            [idp]
+            audience = ""
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("secure_cookie"));
+        assert!(errors[1].contains("idp.audience"));
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn json_in_the_environment_with_camel_case_keys_still_works() {
+        Jail::expect_with(|jail| {
+            jail.set_env(
+                "ARGENT_AUTH",
+                r#"{ "jwtKey": "secret", "secureCookie": true, "cookieName": "legacy",
+                     "allowedOrigins": ["https://argent.example.com"] }"#,
+            );
+            jail.set_env("ARGENT_IDP", r#"{ "audience": "legacy-client" }"#);
+
+            let config = ArgentConfig::from_figment(&ArgentConfig::figment()).unwrap();
+            assert_eq!(config.auth.cookie_name, "legacy");
+            assert!(config.auth.secure_cookie);
+            assert!(config
+                .auth
+                .allowed_origins
+                .matches("https://argent.example.com"));
+            assert_eq!(config.idp.audience, "legacy-client");
+            Ok(())
+        });
+    }
+}
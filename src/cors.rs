@@ -1,10 +1,10 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use rocket::{
     fairing::{Fairing, Info, Kind},
     route::Outcome,
     serde::json::Json,
-    Route,
+    Responder, Route,
 };
 use rocket::{
     http::{Header, Method},
@@ -12,32 +12,63 @@ use rocket::{
 };
 use rocket::{Request, Response};
 
+use crate::config::AllowedOrigins;
+
+/// How long browsers may cache a preflight response, in seconds
+const PREFLIGHT_MAX_AGE: &str = "600";
+
 pub struct CORS {
-    debug: bool,
+    allowed_origins: AllowedOrigins,
 }
 
+#[derive(Responder)]
+struct Preflight {
+    body: Json<&'static str>,
+    allow_methods: Header<'static>,
+    max_age: Header<'static>,
+}
+
+/// Answers preflight requests for one path with the methods of its routes
 #[derive(Clone)]
-pub struct OptionsHandler;
+pub struct OptionsHandler {
+    methods: String,
+}
 
 #[rocket::async_trait]
 impl Handler for OptionsHandler {
     async fn handle<'r>(&self, req: &'r Request<'_>, _data: rocket::data::Data<'r>) -> Outcome<'r> {
-        Outcome::from(req, Json("ok"))
+        Outcome::from(
+            req,
+            Preflight {
+                body: Json("ok"),
+                allow_methods: Header::new("Access-Control-Allow-Methods", self.methods.clone()),
+                max_age: Header::new("Access-Control-Max-Age", PREFLIGHT_MAX_AGE),
+            },
+        )
     }
 }
 
 impl CORS {
-    pub fn init() -> Self {
-        Self {
-            debug: std::env::var("ARGENT_DEBUG").unwrap_or(String::from("false")) == "true",
-        }
+    pub fn new(allowed_origins: AllowedOrigins) -> Self {
+        Self { allowed_origins }
     }
+
     pub fn add_options_method(routes: Vec<Route>) -> Vec<Route> {
-        let unique_routes: HashSet<&str> = routes.iter().map(|r| r.uri.as_str()).collect();
+        let mut methods_by_path: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for route in &routes {
+            methods_by_path
+                .entry(route.uri.path())
+                .or_default()
+                .insert(route.method.as_str());
+        }
 
-        let option_routes = unique_routes
-            .iter()
-            .map(|uri| Route::new(Method::Options, uri, OptionsHandler))
+        let option_routes = methods_by_path
+            .into_iter()
+            .map(|(path, mut methods)| {
+                methods.insert(Method::Options.as_str());
+                let methods = methods.into_iter().collect::<Vec<_>>().join(", ");
+                Route::new(Method::Options, path, OptionsHandler { methods })
+            })
             .collect();
         [routes, option_routes].concat()
     }
@@ -52,21 +83,66 @@ impl Fairing for CORS {
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        let origin = if self.debug {
-            "localhost:8008"
-        } else {
-            "https://argent.grimsborn.com"
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // The response depends on the origin even when it is not allowed
+        response.adjoin_header(Header::new("Vary", "Origin"));
+        let origin = match request.headers().get_one("Origin") {
+            Some(origin) if self.allowed_origins.matches(origin) => origin.to_string(),
+            _ => return,
         };
         response.set_header(Header::new("Access-Control-Allow-Origin", origin));
-        response.set_header(Header::new(
-            "Access-Control-Allow-Methods",
-            "PATCH, OPTIONS, PUT, DELETE",
-        ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
-            "Authorization, Content-Type, X-Forwarded-Proto, X-Request-ID, X-Requested-With",
+            "Authorization, Content-Type, Last-Event-ID, X-Forwarded-Proto, X-Request-ID, X-Requested-With",
         ));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{Header, Status};
+
+    use crate::testing::{TestApp, TEST_ORIGIN};
+
+    #[rocket::async_test]
+    async fn preflight_lists_the_methods_of_the_path() {
+        let app = TestApp::new().await;
+        let response = app
+            .client()
+            .options("/api/v1/checklistitems/5f8d2b5e-5d0e-4b0a-9d43-4a4d1e5a6b7c")
+            .header(Header::new("Origin", TEST_ORIGIN))
+            .header(Header::new("Access-Control-Request-Method", "PATCH"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Methods"),
+            Some("DELETE, OPTIONS, PATCH")
+        );
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some(TEST_ORIGIN)
+        );
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(headers.get_one("Vary"), Some("Origin"));
+    }
+
+    #[rocket::async_test]
+    async fn other_origins_get_no_access() {
+        let app = TestApp::new().await;
+        let response = app
+            .client()
+            .get("/ping")
+            .header(Header::new("Origin", "https://evil.example"))
+            .dispatch()
+            .await;
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Access-Control-Allow-Origin"), None);
+        assert_eq!(headers.get_one("Vary"), Some("Origin"));
+    }
+}
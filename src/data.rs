@@ -1,12 +1,48 @@
 use rocket::{error, fairing, Build, Rocket};
 use rocket_db_pools::Database;
 
+pub mod broadcast;
+pub mod secrets;
+
+pub mod api_tokens {
+    pub mod models;
+    pub mod store;
+}
+
+pub mod chat {
+    pub mod models;
+    pub mod store;
+}
+
 pub mod checklists {
+    pub mod events;
+    pub mod models;
+    pub mod store;
+}
+
+pub mod sessions {
+    pub mod events;
     pub mod models;
     pub mod store;
 }
 
 pub mod users {
+    pub mod cache;
+    pub mod models;
+    pub mod store;
+}
+
+pub mod groups {
+    pub mod models;
+    pub mod store;
+}
+
+pub mod profiles {
+    pub mod models;
+    pub mod store;
+}
+
+pub mod invitations {
     pub mod models;
     pub mod store;
 }
@@ -16,6 +52,11 @@ pub mod marble_game {
     pub mod store;
 }
 
+pub mod wishlists {
+    pub mod models;
+    pub mod store;
+}
+
 #[derive(Database)]
 #[database("argent")]
 pub struct ArgentDB(sqlx::PgPool);
@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use rocket::http::Method;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    data::secrets::{hash_token, random_token},
+    error::ArgentError,
+};
+
+const TOKEN_BYTES: usize = 32;
+/// Makes tokens recognizable, for people and for secret scanners
+const TOKEN_PREFIX: &str = "argent_";
+const MAX_EXPIRES_IN_DAYS: i32 = 3650;
+
+/// What a token may be used for. Read scopes allow `GET` requests,
+/// write scopes everything else, so most scripts need both.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    #[serde(rename = "checklists:read")]
+    ChecklistsRead,
+    #[serde(rename = "checklists:write")]
+    ChecklistsWrite,
+    #[serde(rename = "wishlists:read")]
+    WishlistsRead,
+    #[serde(rename = "wishlists:write")]
+    WishlistsWrite,
+    #[serde(rename = "chat:read")]
+    ChatRead,
+    #[serde(rename = "chat:write")]
+    ChatWrite,
+    #[serde(rename = "users:read")]
+    UsersRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ChecklistsRead => "checklists:read",
+            Scope::ChecklistsWrite => "checklists:write",
+            Scope::WishlistsRead => "wishlists:read",
+            Scope::WishlistsWrite => "wishlists:write",
+            Scope::ChatRead => "chat:read",
+            Scope::ChatWrite => "chat:write",
+            Scope::UsersRead => "users:read",
+        }
+    }
+
+    /// The scope a request to an `/api/v1` path needs. None for everything
+    /// tokens cannot be used for, like managing tokens, sessions or users.
+    pub fn required_for(method: Method, path: &str) -> Option<Scope> {
+        let resource = path
+            .strip_prefix("/api/v1/")?
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let read = matches!(method, Method::Get | Method::Head);
+        match (resource, read) {
+            ("checklists" | "checklistitems", true) => Some(Scope::ChecklistsRead),
+            ("checklists" | "checklistitems", false) => Some(Scope::ChecklistsWrite),
+            ("wishlists" | "wishlist" | "wishlistitems", true) => Some(Scope::WishlistsRead),
+            ("wishlists" | "wishlist" | "wishlistitems", false) => Some(Scope::WishlistsWrite),
+            ("chat", true) => Some(Scope::ChatRead),
+            ("chat", false) => Some(Scope::ChatWrite),
+            ("me" | "users", true) => Some(Scope::UsersRead),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ArgentError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "checklists:read" => Ok(Scope::ChecklistsRead),
+            "checklists:write" => Ok(Scope::ChecklistsWrite),
+            "wishlists:read" => Ok(Scope::WishlistsRead),
+            "wishlists:write" => Ok(Scope::WishlistsWrite),
+            "chat:read" => Ok(Scope::ChatRead),
+            "chat:write" => Ok(Scope::ChatWrite),
+            "users:read" => Ok(Scope::UsersRead),
+            _ => Err(ArgentError::server_error_msg("unknown token scope")),
+        }
+    }
+}
+
+/// Without `expiresInDays` the token is valid until it is revoked
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<i32>,
+}
+
+/// A token as listed to its owner. Times are in milliseconds since the unix epoch.
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub last_used: Option<i64>,
+    pub expires_at: Option<i64>,
+}
+
+/// A newly created token, `token` goes to the user once and only its hash is stored
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<i32>,
+    pub token: String,
+}
+
+impl NewApiToken {
+    pub fn from_request(request: ApiTokenRequest) -> Result<NewApiToken, ArgentError> {
+        let name = request.name.trim().to_string();
+        if name.is_empty() {
+            return Err(ArgentError::bad_request_msg("name cannot be empty"));
+        }
+        if request.scopes.is_empty() {
+            return Err(ArgentError::bad_request_msg(
+                "a token needs at least one scope",
+            ));
+        }
+        if request
+            .expires_in_days
+            .is_some_and(|days| !(1..=MAX_EXPIRES_IN_DAYS).contains(&days))
+        {
+            return Err(ArgentError::bad_request_msg(
+                "expiresInDays must be between 1 and 3650",
+            ));
+        }
+        let mut scopes = Vec::new();
+        for scope in request.scopes {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        Ok(NewApiToken {
+            id: Uuid::new_v4(),
+            name,
+            scopes,
+            expires_in_days: request.expires_in_days,
+            token: format!("{}{}", TOKEN_PREFIX, random_token(TOKEN_BYTES)),
+        })
+    }
+
+    pub fn token_hash(&self) -> String {
+        hash_token(&self.token)
+    }
+
+    pub fn scope_names(&self) -> Vec<&'static str> {
+        self.scopes.iter().map(Scope::as_str).collect()
+    }
+}
+
+/// The owner and scopes of a valid token
+pub struct TokenGrant {
+    pub argent_user: Uuid,
+    pub scopes: Vec<Scope>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_follow_the_resource_and_method() {
+        let required = |method, path| Scope::required_for(method, path);
+        assert_eq!(
+            required(Method::Get, "/api/v1/checklists/1/items"),
+            Some(Scope::ChecklistsRead)
+        );
+        assert_eq!(
+            required(Method::Post, "/api/v1/checklistitems"),
+            Some(Scope::ChecklistsWrite)
+        );
+        assert_eq!(
+            required(Method::Post, "/api/v1/wishlist/share"),
+            Some(Scope::WishlistsWrite)
+        );
+        assert_eq!(required(Method::Get, "/api/v1/me"), Some(Scope::UsersRead));
+        assert_eq!(required(Method::Patch, "/api/v1/me"), None);
+        assert_eq!(required(Method::Get, "/api/v1/tokens"), None);
+        assert_eq!(required(Method::Get, "/api/v1/admin/users"), None);
+        assert_eq!(required(Method::Get, "/checklists"), None);
+    }
+
+    #[test]
+    fn scope_names_round_trip() {
+        for scope in [Scope::ChecklistsRead, Scope::ChatWrite, Scope::UsersRead] {
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+    }
+}
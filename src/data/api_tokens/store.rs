@@ -0,0 +1,128 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{pool::PoolConnection, query, query_as, Postgres, Row};
+use uuid::Uuid;
+
+use crate::{
+    api::helpers::ArgentResult,
+    data::{secrets::hash_token, ArgentDB},
+};
+
+use super::models::{ApiToken, NewApiToken, Scope, TokenGrant};
+
+pub struct ApiTokenStore {
+    db: Connection<ArgentDB>,
+}
+
+impl ApiTokenStore {
+    pub async fn add_token(&mut self, argent_user: Uuid, token: &NewApiToken) -> ArgentResult<()> {
+        query(
+            "INSERT INTO api_tokens (
+                id,
+                argent_user,
+                name,
+                token_hash,
+                scopes,
+                expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, now() + $6 * INTERVAL '1 day')",
+        )
+        .bind(token.id)
+        .bind(argent_user)
+        .bind(&token.name)
+        .bind(token.token_hash())
+        .bind(token.scope_names())
+        .bind(token.expires_in_days)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Tokens that have not expired, newest first
+    pub async fn get_tokens(&mut self, argent_user: Uuid) -> ArgentResult<Vec<ApiToken>> {
+        let tokens = query_as(
+            "SELECT
+                    id,
+                    name,
+                    scopes,
+                    (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT AS created_at,
+                    (EXTRACT(EPOCH FROM last_used) * 1000)::BIGINT AS last_used,
+                    (EXTRACT(EPOCH FROM expires_at) * 1000)::BIGINT AS expires_at
+                FROM api_tokens
+                WHERE argent_user = $1
+                    AND (expires_at IS NULL OR expires_at > now())
+                ORDER BY created_at DESC",
+        )
+        .bind(argent_user)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(tokens)
+    }
+
+    /// Returns false if the user has no such token
+    pub async fn delete_token(&mut self, id: Uuid, argent_user: Uuid) -> ArgentResult<bool> {
+        let result = query(
+            "DELETE FROM api_tokens
+            WHERE id = $1 AND argent_user = $2",
+        )
+        .bind(id)
+        .bind(argent_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The grant of a token that exists and has not expired. Like sessions,
+    /// `last_used` is only written once a minute.
+    pub async fn authenticate_conn(
+        conn: &mut PoolConnection<Postgres>,
+        token: &str,
+    ) -> ArgentResult<Option<TokenGrant>> {
+        let row = query(
+            "SELECT
+                    id,
+                    argent_user,
+                    scopes,
+                    last_used IS NULL OR last_used < now() - INTERVAL '1 minute' AS stale
+                FROM api_tokens
+                WHERE token_hash = $1
+                    AND (expires_at IS NULL OR expires_at > now())",
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&mut *conn)
+        .await?;
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        if row.try_get::<bool, _>("stale")? {
+            query("UPDATE api_tokens SET last_used = now() WHERE id = $1")
+                .bind(row.try_get::<Uuid, _>("id")?)
+                .execute(&mut *conn)
+                .await?;
+        }
+        let scopes = row
+            .try_get::<Vec<String>, _>("scopes")?
+            .iter()
+            .map(|scope| scope.parse::<Scope>())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(TokenGrant {
+            argent_user: row.try_get("argent_user")?,
+            scopes,
+        }))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiTokenStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(ApiTokenStore { db })
+    }
+}
@@ -0,0 +1,30 @@
+use rocket::tokio::sync::broadcast::{channel, Receiver, Sender};
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// In-process fan out of events to every connected stream
+pub struct Broadcast<T: Clone> {
+    sender: Sender<T>,
+}
+
+impl<T: Clone> Broadcast<T> {
+    pub fn new() -> Self {
+        let (sender, _) = channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: T) {
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.sender.subscribe()
+    }
+}
+
+impl<T: Clone> Default for Broadcast<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,130 @@
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::PgRow,
+    types::time::{OffsetDateTime, PrimitiveDateTime},
+    Row,
+};
+use uuid::Uuid;
+
+use crate::{
+    data::{broadcast::Broadcast, users::models::User},
+    error::ArgentError,
+};
+
+const NANOS_PER_MILLI: i128 = 1_000_000;
+/// The range of millisecond timestamps a `PrimitiveDateTime` can hold, years 1 to 9999
+const MIN_MILLIS: i64 = -62_135_596_800_000;
+const MAX_MILLIS: i64 = 253_402_300_799_999;
+
+pub type ChatBroadcast = Broadcast<ChatMessage>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessageRequest {
+    message_text: String,
+}
+
+impl ChatMessageRequest {
+    /// The sender is always the authenticated user, never something from the request
+    pub fn get(self, sender: User) -> Result<ChatMessage, ArgentError> {
+        if self.message_text.trim().is_empty() {
+            return Err(ArgentError::bad_request_msg("message cannot be empty"));
+        }
+        let now_millis = OffsetDateTime::now_utc().unix_timestamp_nanos() / NANOS_PER_MILLI;
+        Ok(ChatMessage {
+            id: Uuid::new_v4(),
+            sender: sender.name,
+            sender_id: sender.id,
+            message_text: self.message_text,
+            created_date: now_millis as i64,
+        })
+    }
+}
+
+/// `created_date` is in milliseconds since the unix epoch
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub id: Uuid,
+    pub sender: String,
+    pub sender_id: Uuid,
+    pub message_text: String,
+    pub created_date: i64,
+}
+
+impl ChatMessage {
+    pub fn from_row(row: &PgRow) -> Result<ChatMessage, ArgentError> {
+        Ok(ChatMessage {
+            id: row.try_get::<Uuid, _>("id")?,
+            sender: row.try_get::<String, _>("sender")?,
+            sender_id: row.try_get::<Uuid, _>("sender_id")?,
+            message_text: row.try_get::<String, _>("message_text")?,
+            created_date: (row
+                .try_get::<PrimitiveDateTime, _>("created_date")?
+                .assume_utc()
+                .unix_timestamp_nanos()
+                / NANOS_PER_MILLI) as i64,
+        })
+    }
+
+    pub fn created_date_primitive_datetime(&self) -> Result<PrimitiveDateTime, ArgentError> {
+        millis_to_primitive_datetime(self.created_date)
+    }
+}
+
+pub fn millis_to_primitive_datetime(millis: i64) -> Result<PrimitiveDateTime, ArgentError> {
+    if !(MIN_MILLIS..=MAX_MILLIS).contains(&millis) {
+        return Err(ArgentError::bad_request_msg("timestamp is out of range"));
+    }
+    let offset_datetime =
+        OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * NANOS_PER_MILLI);
+    Ok(PrimitiveDateTime::new(
+        offset_datetime.date(),
+        offset_datetime.time(),
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatHistory {
+    pub messages: Vec<ChatMessage>,
+    /// Pass as `before` to get the next (older) page, absent when there are no more messages
+    pub next_cursor: Option<String>,
+}
+
+/// The last message of a history page. Messages can share a millisecond,
+/// so the id breaks ties. Clients only see it encoded.
+#[derive(Debug, PartialEq)]
+pub struct HistoryCursor {
+    pub created_date: i64,
+    pub id: Uuid,
+}
+
+impl HistoryCursor {
+    pub fn of(message: &ChatMessage) -> HistoryCursor {
+        HistoryCursor {
+            created_date: message.created_date,
+            id: message.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        base64::encode_config(
+            format!("{}:{}", self.created_date, self.id),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    pub fn decode(cursor: &str) -> Result<HistoryCursor, ArgentError> {
+        let invalid = || ArgentError::bad_request_msg("invalid cursor");
+        let decoded = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let (created_date, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(HistoryCursor {
+            created_date: created_date.parse().map_err(|_| invalid())?,
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
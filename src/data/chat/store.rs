@@ -0,0 +1,134 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{query, Acquire};
+use uuid::Uuid;
+
+use crate::data::ArgentDB;
+use crate::{api::helpers::ArgentResult, error::ArgentError};
+
+use super::models::{millis_to_primitive_datetime, ChatHistory, ChatMessage, HistoryCursor};
+
+pub struct ChatStore {
+    db: Connection<ArgentDB>,
+}
+
+impl ChatStore {
+    /// Messages are added one at a time, so that their `seq` follows the
+    /// order in which they are committed
+    pub async fn add_message(&mut self, message: &ChatMessage) -> ArgentResult<()> {
+        let mut tx = self.db.begin().await?;
+        query("SELECT pg_advisory_xact_lock(hashtext('chat_messages'))")
+            .execute(&mut *tx)
+            .await?;
+        query(
+            "INSERT INTO chat_messages (
+                id,
+                sender,
+                sender_id,
+                message_text,
+                created_date
+            )
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(message.id)
+        .bind(&message.sender)
+        .bind(message.sender_id)
+        .bind(&message.message_text)
+        .bind(message.created_date_primitive_datetime()?)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Newest messages first, only messages older than the `before` cursor if given
+    pub async fn get_history(
+        &mut self,
+        before: Option<HistoryCursor>,
+        limit: i64,
+    ) -> Result<ChatHistory, ArgentError> {
+        let before_date = before
+            .as_ref()
+            .map(|cursor| millis_to_primitive_datetime(cursor.created_date))
+            .transpose()?;
+        let mut messages = query(
+            "SELECT
+                    id,
+                    sender,
+                    sender_id,
+                    message_text,
+                    created_date
+                FROM chat_messages
+                WHERE $1::TIMESTAMP IS NULL OR (created_date, id) < ($1, $2)
+                ORDER BY created_date DESC, id DESC
+                LIMIT $3",
+        )
+        .bind(before_date)
+        .bind(before.map(|cursor| cursor.id))
+        .bind(limit + 1)
+        .fetch_all(&mut *self.db)
+        .await?
+        .iter()
+        .map(ChatMessage::from_row)
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let next_cursor = if messages.len() as i64 > limit {
+            messages.truncate(limit as usize);
+            messages
+                .last()
+                .map(|message| HistoryCursor::of(message).encode())
+        } else {
+            None
+        };
+        Ok(ChatHistory {
+            messages,
+            next_cursor,
+        })
+    }
+
+    /// Messages added after the message with id `last_seen`, in the order
+    /// they were added. Returns nothing if `last_seen` is unknown.
+    pub async fn get_messages_after(
+        &mut self,
+        last_seen: Uuid,
+        limit: i64,
+    ) -> ArgentResult<Vec<ChatMessage>> {
+        query(
+            "SELECT
+                    id,
+                    sender,
+                    sender_id,
+                    message_text,
+                    created_date
+                FROM chat_messages
+                WHERE seq > (
+                    SELECT seq
+                    FROM chat_messages
+                    WHERE id = $1
+                )
+                ORDER BY seq
+                LIMIT $2",
+        )
+        .bind(last_seen)
+        .bind(limit)
+        .fetch_all(&mut *self.db)
+        .await?
+        .iter()
+        .map(ChatMessage::from_row)
+        .collect()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ChatStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(ChatStore { db })
+    }
+}
@@ -0,0 +1,61 @@
+use rocket::serde::Serialize;
+use uuid::Uuid;
+
+use crate::data::broadcast::Broadcast;
+
+use super::models::{AccessType, ChecklistItem};
+
+pub type ChecklistBroadcast = Broadcast<ChecklistEvent>;
+
+/// A change to a checklist, pushed to everyone streaming that checklist
+#[derive(Serialize, Clone, Debug)]
+pub struct ChecklistEvent {
+    pub checklist: Uuid,
+    #[serde(flatten)]
+    pub change: ChecklistChange,
+}
+
+impl ChecklistEvent {
+    pub fn new(checklist: Uuid, change: ChecklistChange) -> Self {
+        Self { checklist, change }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ChecklistChange {
+    ItemCreated {
+        item: ChecklistItem,
+    },
+    ItemDone {
+        item: Uuid,
+        done: bool,
+    },
+    ItemRenamed {
+        item: Uuid,
+        title: String,
+    },
+    ItemDeleted {
+        item: Uuid,
+    },
+    ItemMoved {
+        item: Uuid,
+        position: f64,
+    },
+    Renamed {
+        name: String,
+    },
+    DoneCleared,
+    Shared {
+        user: Uuid,
+        #[serde(rename = "accessType")]
+        access_type: AccessType,
+    },
+    Unshared {
+        user: Uuid,
+    },
+    Deleted,
+    /// The stream fell behind and missed changes, the client should
+    /// fetch the checklist again and reconnect
+    Resync,
+}
@@ -11,17 +11,31 @@ use uuid::Uuid;
 
 use crate::{api::helpers::parse_uuid, error::ArgentError};
 
-#[derive(Serialize, Deserialize, Type, PartialEq)]
+/// Distance between item positions when they are appended or renumbered
+pub const POSITION_GAP: f64 = 1024.0;
+
+#[derive(Serialize, Deserialize, Type, PartialEq, Clone, Copy, Debug)]
 #[sqlx(type_name = "TEXT")]
 pub enum AccessType {
     Owner,
     Editor,
+    Viewer,
     None,
 }
 
+impl AccessType {
+    pub fn can_read(&self) -> bool {
+        matches!(self, Self::Owner | Self::Editor | Self::Viewer)
+    }
+
+    pub fn can_write(&self) -> bool {
+        matches!(self, Self::Owner | Self::Editor)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ChecklistRequest {
-    name: String,
+    pub name: String,
 }
 
 #[derive(Serialize, FromRow)]
@@ -54,11 +68,18 @@ impl ChecklistItemRequest {
             title: self.title,
             checklist,
             done: false,
+            // Set when the item is stored, new items go last
+            position: 0.0,
         })
     }
 }
 
-#[derive(Serialize, FromRow)]
+#[derive(Deserialize)]
+pub struct ChecklistItemUpdateRequest {
+    pub title: String,
+}
+
+#[derive(Serialize, FromRow, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ChecklistItem {
     pub id: Uuid,
@@ -66,6 +87,7 @@ pub struct ChecklistItem {
     pub checklist: Uuid,
     pub done: bool,
     pub created_at: i64,
+    pub position: f64,
 }
 impl ChecklistItem {
     pub fn from_row(row: &PgRow) -> Result<ChecklistItem, ArgentError> {
@@ -78,6 +100,7 @@ impl ChecklistItem {
                 .try_get::<PrimitiveDateTime, _>("created_at")?
                 .assume_utc()
                 .unix_timestamp(),
+            position: row.try_get::<f64, _>("position")?,
         })
     }
     pub fn created_at_primitive_datetime(&self) -> PrimitiveDateTime {
@@ -86,11 +109,34 @@ impl ChecklistItem {
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MoveItemRequest {
+    Before(String),
+    After(String),
+}
+
+/// A position strictly between `lower` and `upper`, where a missing bound means
+/// the start or end of the list. None when the gap is too small and the list
+/// needs to be renumbered.
+pub fn position_between(lower: Option<f64>, upper: Option<f64>) -> Option<f64> {
+    let position = match (lower, upper) {
+        (None, None) => POSITION_GAP,
+        (Some(lower), None) => lower + POSITION_GAP,
+        (None, Some(upper)) => upper - POSITION_GAP,
+        (Some(lower), Some(upper)) => lower + (upper - lower) / 2.0,
+    };
+    let above_lower = lower.is_none_or(|lower| position > lower);
+    let below_upper = upper.is_none_or(|upper| position < upper);
+    (above_lower && below_upper).then_some(position)
+}
+
 #[derive(Serialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct UserAccess {
     id: Uuid,
     name: String,
+    avatar_url: Option<String>,
     pub access_type: AccessType,
 }
 
@@ -100,3 +146,31 @@ pub struct ShareRequest {
     pub user_id: String,
     pub access_type: AccessType,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{position_between, POSITION_GAP};
+
+    #[test]
+    fn position_in_empty_list() {
+        assert_eq!(position_between(None, None), Some(POSITION_GAP));
+    }
+
+    #[test]
+    fn position_at_the_ends() {
+        assert_eq!(position_between(Some(2048.0), None), Some(3072.0));
+        assert_eq!(position_between(None, Some(1024.0)), Some(0.0));
+    }
+
+    #[test]
+    fn position_between_neighbours() {
+        assert_eq!(position_between(Some(1024.0), Some(2048.0)), Some(1536.0));
+    }
+
+    #[test]
+    fn no_position_when_the_gap_is_used_up() {
+        let lower: f64 = 1.0;
+        let upper = f64::from_bits(lower.to_bits() + 1);
+        assert_eq!(position_between(Some(lower), Some(upper)), None);
+    }
+}
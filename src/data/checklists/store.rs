@@ -1,8 +1,10 @@
 use std::convert::Infallible;
 
+use anyhow::anyhow;
+
 use rocket::request::FromRequest;
 use rocket_db_pools::Connection;
-use sqlx::{Acquire, Row};
+use sqlx::{pool::PoolConnection, Acquire, Postgres, Row};
 use uuid::Uuid;
 
 use crate::{
@@ -11,7 +13,9 @@ use crate::{
     error::ArgentError,
 };
 
-use super::models::{AccessType, Checklist, ChecklistItem, UserAccess};
+use super::models::{
+    position_between, AccessType, Checklist, ChecklistItem, UserAccess, POSITION_GAP,
+};
 
 pub struct ChecklistStore {
     db: Connection<ArgentDB>,
@@ -28,9 +32,11 @@ impl ChecklistStore {
                         title,
                         done,
                         created_at,
-                        checklist
+                        checklist,
+                        position
                     FROM checklistitems
-                    WHERE checklist = $1",
+                    WHERE checklist = $1
+                    ORDER BY position, created_at",
         )
         .bind(checklist)
         .fetch_all(&mut *self.db)
@@ -91,7 +97,15 @@ impl ChecklistStore {
         checklist: Checklist,
         user: User,
     ) -> Result<(), ArgentError> {
-        let mut tx = self.db.begin().await?;
+        Self::create_checklist_conn(&mut self.db, checklist, user.id).await
+    }
+
+    pub async fn create_checklist_conn(
+        conn: &mut PoolConnection<Postgres>,
+        checklist: Checklist,
+        owner: Uuid,
+    ) -> ArgentResult<()> {
+        let mut tx = conn.begin().await?;
         sqlx::query(
             "INSERT INTO checklists (
                     id,
@@ -113,7 +127,7 @@ impl ChecklistStore {
             VALUES($1,$2,$3)",
         )
         .bind(checklist.id)
-        .bind(user.id)
+        .bind(owner)
         .bind(AccessType::Owner)
         .execute(&mut *tx)
         .await?;
@@ -122,6 +136,19 @@ impl ChecklistStore {
         Ok(())
     }
 
+    pub async fn rename_checklist(&mut self, checklist: Uuid, name: &str) -> ArgentResult<()> {
+        sqlx::query(
+            "UPDATE checklists
+            SET name = $1
+            WHERE id = $2",
+        )
+        .bind(name)
+        .bind(checklist)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
     pub async fn add_user_access(
         &mut self,
         checklist_id: Uuid,
@@ -167,25 +194,157 @@ impl ChecklistStore {
         Ok(())
     }
 
-    pub async fn add_item(&mut self, item: ChecklistItem) -> Result<(), ArgentError> {
-        sqlx::query(
+    /// Adds the item last in its checklist and returns the position it got
+    pub async fn add_item(&mut self, item: &ChecklistItem) -> Result<f64, ArgentError> {
+        Self::add_item_conn(&mut self.db, item).await
+    }
+
+    pub async fn add_item_conn(
+        conn: &mut PoolConnection<Postgres>,
+        item: &ChecklistItem,
+    ) -> ArgentResult<f64> {
+        let row = sqlx::query(
             "INSERT INTO checklistitems (
                 id,
                 title,
                 done,
                 checklist,
-                created_at
+                created_at,
+                position
+            )
+            VALUES (
+                $1,$2,$3,$4,$5,
+                COALESCE(
+                    (SELECT MAX(position) FROM checklistitems WHERE checklist = $4),
+                    0
+                ) + $6
             )
-            VALUES ($1,$2,$3,$4,$5)",
+            RETURNING position",
         )
-        .bind(&item.id)
+        .bind(item.id)
         .bind(&item.title)
-        .bind(&item.done)
-        .bind(&item.checklist)
+        .bind(item.done)
+        .bind(item.checklist)
         .bind(item.created_at_primitive_datetime())
-        .execute(&mut *self.db)
+        .bind(POSITION_GAP)
+        .fetch_one(&mut *conn)
         .await?;
-        Ok(())
+        Ok(row.try_get("position")?)
+    }
+
+    /// Moves the item next to `anchor` in the same checklist and returns its new position.
+    /// Only the moved item is updated unless the positions around the anchor are used up,
+    /// then the checklist is renumbered first.
+    pub async fn move_item(
+        &mut self,
+        item_id: Uuid,
+        checklist: Uuid,
+        anchor_id: Uuid,
+        before_anchor: bool,
+    ) -> ArgentResult<f64> {
+        let mut tx = self.db.begin().await?;
+        // Serialize moves within a checklist
+        sqlx::query("SELECT id FROM checklists WHERE id = $1 FOR UPDATE")
+            .bind(checklist)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut renumbered = false;
+        let position = loop {
+            let anchor = sqlx::query(
+                "SELECT position
+                    FROM checklistitems
+                    WHERE id = $1
+                    AND checklist = $2",
+            )
+            .bind(anchor_id)
+            .bind(checklist)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| ArgentError::bad_request_msg("cannot move next to that item"))?
+            .try_get::<f64, _>("position")?;
+
+            let neighbour_query = if before_anchor {
+                "SELECT position
+                    FROM checklistitems
+                    WHERE checklist = $1
+                    AND position < $2
+                    AND id <> $3
+                    ORDER BY position DESC
+                    LIMIT 1"
+            } else {
+                "SELECT position
+                    FROM checklistitems
+                    WHERE checklist = $1
+                    AND position > $2
+                    AND id <> $3
+                    ORDER BY position
+                    LIMIT 1"
+            };
+            let neighbour = sqlx::query(neighbour_query)
+                .bind(checklist)
+                .bind(anchor)
+                .bind(item_id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|row| row.try_get::<f64, _>("position"))
+                .transpose()?;
+
+            let (lower, upper) = if before_anchor {
+                (neighbour, Some(anchor))
+            } else {
+                (Some(anchor), neighbour)
+            };
+            match position_between(lower, upper) {
+                Some(position) => break position,
+                None if !renumbered => {
+                    sqlx::query(
+                        "UPDATE checklistitems ci
+                        SET position = ordered.row_number * $2
+                        FROM (
+                            SELECT id, ROW_NUMBER() OVER (ORDER BY position, created_at, id) AS row_number
+                            FROM checklistitems
+                            WHERE checklist = $1
+                        ) ordered
+                        WHERE ci.id = ordered.id",
+                    )
+                    .bind(checklist)
+                    .bind(POSITION_GAP)
+                    .execute(&mut *tx)
+                    .await?;
+                    renumbered = true;
+                }
+                None => return Err(anyhow!("no room for item after renumbering").into()),
+            }
+        };
+
+        sqlx::query(
+            "UPDATE checklistitems
+            SET position = $1
+            WHERE id = $2",
+        )
+        .bind(position)
+        .bind(item_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(position)
+    }
+
+    pub async fn get_checklist_for_item(&mut self, item_id: Uuid) -> ArgentResult<Uuid> {
+        let row = sqlx::query(
+            "SELECT checklist
+                FROM checklistitems
+                WHERE id = $1",
+        )
+        .bind(item_id)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        match row {
+            Some(row) => Ok(row.try_get("checklist")?),
+            None => Err(ArgentError::not_found()),
+        }
     }
 
     pub async fn set_item_done(&mut self, item_id: Uuid, done: bool) -> Result<(), ArgentError> {
@@ -201,6 +360,30 @@ impl ChecklistStore {
         Ok(())
     }
 
+    pub async fn set_item_title(&mut self, item_id: Uuid, title: &str) -> ArgentResult<()> {
+        sqlx::query(
+            "UPDATE checklistitems
+            SET title = $1
+            WHERE id = $2",
+        )
+        .bind(title)
+        .bind(item_id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_item(&mut self, item_id: Uuid) -> ArgentResult<()> {
+        sqlx::query(
+            "DELETE FROM checklistitems
+                WHERE id = $1",
+        )
+        .bind(item_id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
     pub async fn clear_done(&mut self, checklist: Uuid) -> Result<(), ArgentError> {
         sqlx::query(
             "DELETE FROM checklistitems
@@ -261,10 +444,13 @@ impl ChecklistStore {
             "SELECT
                 id,
                 name,
+                '/api/v1/users/' || u.id || '/avatar?v=' || av.version AS avatar_url,
                 access_type
             FROM argent_users u
             LEFT JOIN checklist_access ca
             ON ca.argent_user = u.id
+            LEFT JOIN user_avatars av
+            ON av.argent_user = u.id
             WHERE ca.checklist = $1",
         )
         .bind(checklist)
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Type};
+use uuid::Uuid;
+
+use crate::error::ArgentError;
+
+/// Owners manage the membership of their group, admins manage all groups
+#[derive(Serialize, Deserialize, Type, Clone, Copy, Debug, PartialEq)]
+#[sqlx(type_name = "TEXT")]
+pub enum GroupRole {
+    Owner,
+    Member,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Group {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// A group the way one of its members sees it
+#[derive(Serialize, FromRow)]
+pub struct MemberGroup {
+    pub id: Uuid,
+    pub name: String,
+    pub role: GroupRole,
+}
+
+/// `avatar_url` is selected like it is for `UserForSharing`
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMember {
+    pub id: Uuid,
+    pub name: String,
+    pub avatar_url: Option<String>,
+    pub role: GroupRole,
+}
+
+/// A checklist share revoked because its users no longer share a group
+#[derive(FromRow)]
+pub struct RevokedShare {
+    pub checklist: Uuid,
+    pub argent_user: Uuid,
+}
+
+/// The optional owner becomes the first member
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupRequest {
+    pub name: String,
+    pub owner_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct GroupUpdateRequest {
+    pub name: String,
+}
+
+/// Adds the user, or changes their role if they are a member already
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMemberRequest {
+    pub user_id: String,
+    pub role: GroupRole,
+}
+
+impl Group {
+    pub fn new(name: String) -> Result<Group, ArgentError> {
+        Ok(Group {
+            id: Uuid::new_v4(),
+            name: validate_name(name)?,
+        })
+    }
+
+    pub fn rename(self, name: String) -> Result<Group, ArgentError> {
+        Ok(Group {
+            id: self.id,
+            name: validate_name(name)?,
+        })
+    }
+}
+
+fn validate_name(name: String) -> Result<String, ArgentError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        Err(ArgentError::bad_request_msg("name cannot be empty"))
+    } else {
+        Ok(name)
+    }
+}
@@ -0,0 +1,395 @@
+use std::convert::Infallible;
+
+use rocket::{http::Status, request::FromRequest};
+use rocket_db_pools::Connection;
+use sqlx::{pool::PoolConnection, query, query_as, Acquire, Postgres, Row, Transaction};
+use uuid::Uuid;
+
+use crate::data::{checklists::models::AccessType, ArgentDB};
+use crate::{api::helpers::ArgentResult, error::ArgentError};
+
+use super::models::{Group, GroupMember, GroupRole, MemberGroup, RevokedShare};
+
+/// Locks the owners of the group until the transaction ends and refuses if
+/// `user_id` is the only one, so that a group with owners keeps one
+async fn check_other_owner_remains(
+    tx: &mut Transaction<'_, Postgres>,
+    group_id: Uuid,
+    user_id: Uuid,
+) -> ArgentResult<()> {
+    let owners = query(
+        "SELECT argent_user
+            FROM group_members
+            WHERE group_id = $1 AND role = $2
+            FOR UPDATE",
+    )
+    .bind(group_id)
+    .bind(GroupRole::Owner)
+    .fetch_all(&mut *tx)
+    .await?
+    .iter()
+    .map(|row| row.try_get::<Uuid, _>("argent_user"))
+    .collect::<Result<Vec<_>, _>>()?;
+    if owners.contains(&user_id) && owners.len() == 1 {
+        Err(ArgentError::new(
+            "cannot remove the last owner of a group",
+            Status::Conflict,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Users only share with people in one of their groups, so shares that
+/// involve `user_ids` and no longer meet that are revoked. A checklist
+/// share stays as long as any owner of the checklist shares a group with
+/// the user. Returns the revoked checklist shares.
+async fn revoke_shares_outside_groups(
+    tx: &mut Transaction<'_, Postgres>,
+    user_ids: &[Uuid],
+) -> ArgentResult<Vec<RevokedShare>> {
+    let revoked = query_as(
+        "DELETE FROM checklist_access shared
+        WHERE shared.access_type <> $2
+            AND (
+                shared.argent_user = ANY($1)
+                OR EXISTS (
+                    SELECT 1
+                    FROM checklist_access owner
+                    WHERE owner.checklist = shared.checklist
+                        AND owner.access_type = $2
+                        AND owner.argent_user = ANY($1)
+                )
+            )
+            AND NOT EXISTS (
+                SELECT 1
+                FROM checklist_access owner
+                JOIN group_members mine
+                ON mine.argent_user = owner.argent_user
+                JOIN group_members theirs
+                ON theirs.group_id = mine.group_id
+                WHERE owner.checklist = shared.checklist
+                    AND owner.access_type = $2
+                    AND theirs.argent_user = shared.argent_user
+            )
+        RETURNING shared.checklist, shared.argent_user",
+    )
+    .bind(user_ids)
+    .bind(AccessType::Owner)
+    .fetch_all(&mut *tx)
+    .await?;
+    query(
+        "DELETE FROM wishlist_access wa
+        WHERE (wa.wishlist_user = ANY($1) OR wa.access_user = ANY($1))
+            AND NOT EXISTS (
+                SELECT 1
+                FROM group_members mine
+                JOIN group_members theirs
+                ON theirs.group_id = mine.group_id
+                WHERE mine.argent_user = wa.wishlist_user
+                    AND theirs.argent_user = wa.access_user
+            )",
+    )
+    .bind(user_ids)
+    .execute(&mut *tx)
+    .await?;
+    Ok(revoked)
+}
+
+pub struct GroupStore {
+    db: Connection<ArgentDB>,
+}
+
+impl GroupStore {
+    pub async fn create_group(&mut self, group: &Group, owner: Option<Uuid>) -> ArgentResult<()> {
+        Self::create_group_conn(&mut self.db, group, owner).await
+    }
+
+    pub async fn create_group_conn(
+        conn: &mut PoolConnection<Postgres>,
+        group: &Group,
+        owner: Option<Uuid>,
+    ) -> ArgentResult<()> {
+        let mut tx = conn.begin().await?;
+        query(
+            "INSERT INTO groups (
+                id,
+                name
+            )
+            VALUES ($1, $2)",
+        )
+        .bind(group.id)
+        .bind(&group.name)
+        .execute(&mut *tx)
+        .await?;
+        if let Some(owner) = owner {
+            let added = query(
+                "INSERT INTO group_members (
+                    group_id,
+                    argent_user,
+                    role
+                )
+                SELECT $1, id, $3
+                FROM argent_users
+                WHERE id = $2",
+            )
+            .bind(group.id)
+            .bind(owner)
+            .bind(GroupRole::Owner)
+            .execute(&mut *tx)
+            .await?;
+            if added.rows_affected() == 0 {
+                return Err(ArgentError::bad_request_msg("owner does not exist"));
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_groups(&mut self) -> ArgentResult<Vec<Group>> {
+        let groups = query_as(
+            "SELECT
+                id,
+                name
+            FROM groups
+            ORDER BY name",
+        )
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(groups)
+    }
+
+    pub async fn get_groups_for_user(&mut self, user_id: Uuid) -> ArgentResult<Vec<MemberGroup>> {
+        let groups = query_as(
+            "SELECT
+                id,
+                name,
+                role
+            FROM groups g
+            JOIN group_members gm
+            ON gm.group_id = g.id
+            WHERE gm.argent_user = $1
+            ORDER BY name",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(groups)
+    }
+
+    pub async fn get_group(&mut self, id: Uuid) -> ArgentResult<Group> {
+        query_as(
+            "SELECT
+                    id,
+                    name
+                FROM groups
+                WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&mut *self.db)
+        .await?
+        .ok_or_else(|| ArgentError::not_found_msg("group not found"))
+    }
+
+    /// The oldest group with the name, names are not unique
+    pub async fn find_group_by_name_conn(
+        conn: &mut PoolConnection<Postgres>,
+        name: &str,
+    ) -> ArgentResult<Option<Group>> {
+        let group = query_as(
+            "SELECT
+                    id,
+                    name
+                FROM groups
+                WHERE name = $1
+                ORDER BY created_at
+                LIMIT 1",
+        )
+        .bind(name)
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(group)
+    }
+
+    pub async fn update_group(&mut self, group: &Group) -> ArgentResult<()> {
+        query(
+            "UPDATE groups
+            SET name = $2
+            WHERE id = $1",
+        )
+        .bind(group.id)
+        .bind(&group.name)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns None if there was no such group. Shares between the members
+    /// that relied on the group are revoked and the checklist ones returned.
+    pub async fn delete_group(&mut self, id: Uuid) -> ArgentResult<Option<Vec<RevokedShare>>> {
+        let mut tx = self.db.begin().await?;
+        let members = query(
+            "SELECT argent_user
+                FROM group_members
+                WHERE group_id = $1",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|row| row.try_get::<Uuid, _>("argent_user"))
+        .collect::<Result<Vec<_>, _>>()?;
+        let result = query(
+            "DELETE FROM groups
+            WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        let revoked = revoke_shares_outside_groups(&mut tx, &members).await?;
+        tx.commit().await?;
+        Ok(Some(revoked))
+    }
+
+    pub async fn get_members(&mut self, group_id: Uuid) -> ArgentResult<Vec<GroupMember>> {
+        let members = query_as(
+            "SELECT
+                id,
+                name,
+                '/api/v1/users/' || u.id || '/avatar?v=' || av.version AS avatar_url,
+                gm.role
+            FROM argent_users u
+            JOIN group_members gm
+            ON gm.argent_user = u.id
+            LEFT JOIN user_avatars av
+            ON av.argent_user = u.id
+            WHERE gm.group_id = $1
+            ORDER BY name",
+        )
+        .bind(group_id)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(members)
+    }
+
+    /// None if the user is not a member of the group
+    pub async fn get_role(
+        &mut self,
+        group_id: Uuid,
+        user_id: Uuid,
+    ) -> ArgentResult<Option<GroupRole>> {
+        let row = query(
+            "SELECT role
+                FROM group_members
+                WHERE group_id = $1 AND argent_user = $2",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(row.map(|row| row.try_get("role")).transpose()?)
+    }
+
+    /// Adds the user to the group or changes their role in it,
+    /// refused if it would leave the group without its last owner
+    pub async fn set_member(
+        &mut self,
+        group_id: Uuid,
+        user_id: Uuid,
+        role: GroupRole,
+    ) -> ArgentResult<()> {
+        Self::set_member_conn(&mut self.db, group_id, user_id, role).await
+    }
+
+    pub async fn set_member_conn(
+        conn: &mut PoolConnection<Postgres>,
+        group_id: Uuid,
+        user_id: Uuid,
+        role: GroupRole,
+    ) -> ArgentResult<()> {
+        let mut tx = conn.begin().await?;
+        if role != GroupRole::Owner {
+            check_other_owner_remains(&mut tx, group_id, user_id).await?;
+        }
+        let result = query(
+            "INSERT INTO group_members (
+                group_id,
+                argent_user,
+                role
+            )
+            SELECT $1, id, $3
+            FROM argent_users
+            WHERE id = $2
+            ON CONFLICT (group_id, argent_user)
+            DO UPDATE SET role = EXCLUDED.role",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(ArgentError::not_found_msg("user not found"));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns None if the user was not a member, refused for the last owner.
+    /// Shares with the user that relied on the group are revoked and the
+    /// checklist ones returned.
+    pub async fn remove_member(
+        &mut self,
+        group_id: Uuid,
+        user_id: Uuid,
+    ) -> ArgentResult<Option<Vec<RevokedShare>>> {
+        let mut tx = self.db.begin().await?;
+        check_other_owner_remains(&mut tx, group_id, user_id).await?;
+        let result = query(
+            "DELETE FROM group_members
+            WHERE group_id = $1 AND argent_user = $2",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        let revoked = revoke_shares_outside_groups(&mut tx, &[user_id]).await?;
+        tx.commit().await?;
+        Ok(Some(revoked))
+    }
+
+    /// Users may only find and share with people they share a group with
+    pub async fn shares_group(&mut self, user_id: Uuid, other_id: Uuid) -> ArgentResult<bool> {
+        let row = query(
+            "SELECT 1
+                FROM group_members mine
+                JOIN group_members theirs
+                ON theirs.group_id = mine.group_id
+                WHERE mine.argent_user = $1 AND theirs.argent_user = $2
+                LIMIT 1",
+        )
+        .bind(user_id)
+        .bind(other_id)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(row.is_some())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for GroupStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(GroupStore { db })
+    }
+}
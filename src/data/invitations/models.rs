@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    data::{
+        secrets::{hash_token, random_token},
+        users::models::UserRole,
+    },
+    error::ArgentError,
+};
+
+const CODE_BYTES: usize = 16;
+
+/// Without an email the invitation gets a code that anyone can redeem once.
+/// Without a group the invited person joins the inviter's group.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationRequest {
+    pub email: Option<String>,
+    pub role: UserRole,
+    pub group_id: Option<String>,
+}
+
+/// A pending invitation. Times are in milliseconds since the unix epoch.
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub id: Uuid,
+    pub email: Option<String>,
+    pub role: UserRole,
+    pub invited_by: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+/// An invitation as stored, the code itself is only known to its creator
+pub struct NewInvitation {
+    pub id: Uuid,
+    pub email: Option<String>,
+    pub code: Option<String>,
+    pub role: UserRole,
+    pub invited_by: Uuid,
+    pub group_id: Option<Uuid>,
+}
+
+impl NewInvitation {
+    pub fn from_request(
+        request: InvitationRequest,
+        invited_by: Uuid,
+        group_id: Option<Uuid>,
+    ) -> Result<NewInvitation, ArgentError> {
+        let email = request.email.map(|email| email.trim().to_string());
+        if email.as_ref().is_some_and(|email| !email.contains('@')) {
+            return Err(ArgentError::bad_request_msg("email is not valid"));
+        }
+        let code = match email {
+            Some(_) => None,
+            None => Some(random_token(CODE_BYTES)),
+        };
+        Ok(NewInvitation {
+            id: Uuid::new_v4(),
+            email,
+            code,
+            role: request.role,
+            invited_by,
+            group_id,
+        })
+    }
+
+    pub fn code_hash(&self) -> Option<String> {
+        self.code.as_deref().map(hash_token)
+    }
+}
+
+/// Returned once when an invitation is created, `code` is never shown again
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedInvitation {
+    pub id: Uuid,
+    pub email: Option<String>,
+    pub code: Option<String>,
+    pub role: UserRole,
+    pub group_id: Option<Uuid>,
+}
+
+impl CreatedInvitation {
+    pub fn from_invitation(invitation: NewInvitation) -> Self {
+        CreatedInvitation {
+            id: invitation.id,
+            email: invitation.email,
+            code: invitation.code,
+            role: invitation.role,
+            group_id: invitation.group_id,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{query, query_as, Acquire, Row};
+use uuid::Uuid;
+
+use crate::{
+    api::helpers::ArgentResult,
+    data::{
+        groups::models::GroupRole,
+        secrets::hash_token,
+        users::models::{User, UserRole},
+        ArgentDB,
+    },
+};
+
+use super::models::{Invitation, NewInvitation};
+
+/// How long an invitation can be accepted
+pub const INVITATION_DAYS: i32 = 14;
+
+pub struct InvitationStore {
+    db: Connection<ArgentDB>,
+}
+
+impl InvitationStore {
+    pub async fn add_invitation(&mut self, invitation: &NewInvitation) -> ArgentResult<()> {
+        query(
+            "INSERT INTO invitations (
+                id,
+                email,
+                code_hash,
+                role,
+                invited_by,
+                group_id,
+                expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, now() + $7 * INTERVAL '1 day')",
+        )
+        .bind(invitation.id)
+        .bind(&invitation.email)
+        .bind(invitation.code_hash())
+        .bind(&invitation.role)
+        .bind(invitation.invited_by)
+        .bind(invitation.group_id)
+        .bind(INVITATION_DAYS)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Invitations that can still be accepted, only those by `invited_by` if given
+    pub async fn get_pending_invitations(
+        &mut self,
+        invited_by: Option<Uuid>,
+    ) -> ArgentResult<Vec<Invitation>> {
+        let invitations = query_as(
+            "SELECT
+                    id,
+                    email,
+                    role,
+                    invited_by,
+                    group_id,
+                    (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT AS created_at,
+                    (EXTRACT(EPOCH FROM expires_at) * 1000)::BIGINT AS expires_at
+                FROM invitations
+                WHERE accepted_at IS NULL
+                    AND expires_at > now()
+                    AND ($1::UUID IS NULL OR invited_by = $1)
+                ORDER BY created_at DESC",
+        )
+        .bind(invited_by)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(invitations)
+    }
+
+    /// Returns false if there is no such pending invitation by `invited_by`,
+    /// or by anyone if not given
+    pub async fn delete_invitation(
+        &mut self,
+        id: Uuid,
+        invited_by: Option<Uuid>,
+    ) -> ArgentResult<bool> {
+        let result = query(
+            "DELETE FROM invitations
+            WHERE id = $1
+                AND accepted_at IS NULL
+                AND ($2::UUID IS NULL OR invited_by = $2)",
+        )
+        .bind(id)
+        .bind(invited_by)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Creates the account for someone logging in for the first time, using
+    /// up an invitation for their email or, failing that, the given code.
+    /// They join the group of the invitation. Returns None if they were not
+    /// invited, or if a login running at the same time created their account.
+    pub async fn accept_invitation(
+        &mut self,
+        email: &str,
+        name: &str,
+        code: Option<&str>,
+    ) -> ArgentResult<Option<User>> {
+        let mut tx = self.db.begin().await?;
+        let invitation = query(
+            "SELECT id, role, group_id
+            FROM invitations
+            WHERE accepted_at IS NULL
+                AND expires_at > now()
+                AND (lower(email) = lower($1) OR (email IS NULL AND code_hash = $2))
+            ORDER BY email IS NULL, created_at
+            LIMIT 1
+            FOR UPDATE",
+        )
+        .bind(email)
+        .bind(code.map(hash_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let (invitation, role, group_id) = match invitation {
+            Some(row) => (
+                row.try_get::<Uuid, _>("id")?,
+                row.try_get::<UserRole, _>("role")?,
+                row.try_get::<Option<Uuid>, _>("group_id")?,
+            ),
+            None => return Ok(None),
+        };
+        let user = User {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            email: email.to_string(),
+            role,
+        };
+        let inserted = query(
+            "INSERT INTO argent_users (
+                id,
+                name,
+                email,
+                role
+            )
+            VALUES ($1, $2, $3, $4)",
+        )
+        .bind(user.id)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(&user.role)
+        .execute(&mut *tx)
+        .await;
+        match inserted {
+            Err(sqlx::Error::Database(error))
+                if error.constraint() == Some("argent_users_email") =>
+            {
+                return Ok(None)
+            }
+            inserted => inserted?,
+        };
+        if let Some(group_id) = group_id {
+            query(
+                "INSERT INTO group_members (
+                    group_id,
+                    argent_user,
+                    role
+                )
+                VALUES ($1, $2, $3)",
+            )
+            .bind(group_id)
+            .bind(user.id)
+            .bind(GroupRole::Member)
+            .execute(&mut *tx)
+            .await?;
+        }
+        query(
+            "UPDATE invitations
+            SET accepted_at = now(), accepted_by = $2
+            WHERE id = $1",
+        )
+        .bind(invitation)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InvitationStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(InvitationStore { db })
+    }
+}
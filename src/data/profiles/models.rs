@@ -0,0 +1,185 @@
+use rocket::http::ContentType;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::error::ArgentError;
+
+const MAX_LOCALE_LENGTH: usize = 35;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChecklistSortOrder {
+    #[default]
+    Manual,
+    Alphabetical,
+    Created,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Theme {
+    Light,
+    Dark,
+    #[default]
+    System,
+}
+
+/// Stored as JSON, anything missing from the stored document is the default
+/// and stored keys that are no longer known are ignored
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserPreferences {
+    pub checklist_sort_order: ChecklistSortOrder,
+    pub theme: Theme,
+    /// A BCP 47 language tag like `en-GB`, the browser's language when absent
+    pub locale: Option<String>,
+}
+
+/// Fields left out stay as they are, an empty `locale` goes back to the browser's language
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct UserPreferencesRequest {
+    pub checklist_sort_order: Option<ChecklistSortOrder>,
+    pub theme: Option<Theme>,
+    pub locale: Option<String>,
+}
+
+impl UserPreferences {
+    pub fn update_from_request(
+        self,
+        request: UserPreferencesRequest,
+    ) -> Result<UserPreferences, ArgentError> {
+        let locale = match request.locale.map(|locale| locale.trim().to_string()) {
+            Some(locale) if locale.is_empty() => None,
+            Some(locale) => {
+                validate_locale(&locale)?;
+                Some(locale)
+            }
+            None => self.locale,
+        };
+        Ok(UserPreferences {
+            checklist_sort_order: request
+                .checklist_sort_order
+                .unwrap_or(self.checklist_sort_order),
+            theme: request.theme.unwrap_or(self.theme),
+            locale,
+        })
+    }
+}
+
+fn validate_locale(locale: &str) -> Result<(), ArgentError> {
+    let valid = locale.len() <= MAX_LOCALE_LENGTH
+        && locale.split('-').all(|part| {
+            !part.is_empty() && part.len() <= 8 && part.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(ArgentError::bad_request_msg("locale is not valid"))
+    }
+}
+
+/// An uploaded avatar. `version` changes with every upload.
+#[derive(FromRow)]
+pub struct Avatar {
+    pub content_type: String,
+    pub image: Vec<u8>,
+    pub version: i64,
+}
+
+/// Checks the image itself rather than trusting the declared content type,
+/// avatars are served to other users so they must really be images
+pub fn avatar_content_type(
+    declared: Option<&ContentType>,
+    image: &[u8],
+) -> Result<ContentType, ArgentError> {
+    let detected = if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        ContentType::PNG
+    } else if image.starts_with(&[0xff, 0xd8, 0xff]) {
+        ContentType::JPEG
+    } else if image.starts_with(b"GIF87a") || image.starts_with(b"GIF89a") {
+        ContentType::GIF
+    } else if image.len() >= 12 && image.starts_with(b"RIFF") && &image[8..12] == b"WEBP" {
+        ContentType::WEBP
+    } else {
+        return Err(ArgentError::bad_request_msg(
+            "avatar must be a png, jpeg, gif or webp image",
+        ));
+    };
+    match declared {
+        Some(declared) if *declared != detected => Err(ArgentError::bad_request_msg(
+            "avatar does not match its content type",
+        )),
+        _ => Ok(detected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(locale: Option<&str>) -> UserPreferencesRequest {
+        UserPreferencesRequest {
+            checklist_sort_order: None,
+            theme: Some(Theme::Dark),
+            locale: locale.map(String::from),
+        }
+    }
+
+    #[test]
+    fn preferences_keep_what_is_not_in_the_request() {
+        let preferences = UserPreferences {
+            checklist_sort_order: ChecklistSortOrder::Alphabetical,
+            theme: Theme::Light,
+            locale: Some(String::from("nl-NL")),
+        };
+
+        let updated = preferences.update_from_request(request(None)).unwrap();
+        assert_eq!(
+            updated.checklist_sort_order,
+            ChecklistSortOrder::Alphabetical
+        );
+        assert_eq!(updated.theme, Theme::Dark);
+        assert_eq!(updated.locale.as_deref(), Some("nl-NL"));
+
+        let updated = updated.update_from_request(request(Some(""))).unwrap();
+        assert_eq!(updated.locale, None);
+    }
+
+    #[test]
+    fn preferences_reject_invalid_locales() {
+        let preferences = UserPreferences::default();
+        assert!(preferences
+            .clone()
+            .update_from_request(request(Some("en-GB")))
+            .is_ok());
+        assert!(preferences
+            .clone()
+            .update_from_request(request(Some("en_GB")))
+            .is_err());
+        assert!(preferences
+            .update_from_request(request(Some("<script>")))
+            .is_err());
+    }
+
+    #[test]
+    fn stored_preferences_fall_back_to_defaults() {
+        let preferences: UserPreferences =
+            rocket::serde::json::from_str(r#"{"theme": "dark", "removedPreference": true}"#)
+                .unwrap();
+        assert_eq!(preferences.theme, Theme::Dark);
+        assert_eq!(preferences.checklist_sort_order, ChecklistSortOrder::Manual);
+    }
+
+    #[test]
+    fn avatar_content_type_is_detected_from_the_image() {
+        let png = b"\x89PNG\r\n\x1a\nrest";
+        assert_eq!(avatar_content_type(None, png).unwrap(), ContentType::PNG);
+        assert_eq!(
+            avatar_content_type(Some(&ContentType::PNG), png).unwrap(),
+            ContentType::PNG
+        );
+        assert!(avatar_content_type(Some(&ContentType::JPEG), png).is_err());
+        assert!(avatar_content_type(Some(&ContentType::HTML), b"<html>").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{query, query_as, types::Json, Row};
+use uuid::Uuid;
+
+use crate::data::ArgentDB;
+use crate::{api::helpers::ArgentResult, error::ArgentError};
+
+use super::models::{Avatar, UserPreferences};
+
+pub struct ProfileStore {
+    db: Connection<ArgentDB>,
+}
+
+impl ProfileStore {
+    /// The defaults for users that never changed a preference
+    pub async fn get_preferences(&mut self, user_id: Uuid) -> ArgentResult<UserPreferences> {
+        let row = query(
+            "SELECT preferences
+                FROM user_preferences
+                WHERE argent_user = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        match row {
+            Some(row) => Ok(row.try_get::<Json<UserPreferences>, _>("preferences")?.0),
+            None => Ok(UserPreferences::default()),
+        }
+    }
+
+    pub async fn save_preferences(
+        &mut self,
+        user_id: Uuid,
+        preferences: &UserPreferences,
+    ) -> ArgentResult<()> {
+        query(
+            "INSERT INTO user_preferences (
+                argent_user,
+                preferences
+            )
+            VALUES ($1, $2)
+            ON CONFLICT (argent_user)
+            DO UPDATE SET preferences = EXCLUDED.preferences",
+        )
+        .bind(user_id)
+        .bind(Json(preferences))
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Avatars are shown wherever users see each other: in a group, on a
+    /// checklist they both have access to or on a wishlist shared between them
+    pub async fn can_see_avatar(&mut self, viewer: Uuid, avatar_user: Uuid) -> ArgentResult<bool> {
+        if viewer == avatar_user {
+            return Ok(true);
+        }
+        let row = query(
+            "SELECT 1
+            WHERE EXISTS (
+                SELECT 1
+                FROM group_members mine
+                JOIN group_members theirs
+                ON theirs.group_id = mine.group_id
+                WHERE mine.argent_user = $1 AND theirs.argent_user = $2
+            )
+            OR EXISTS (
+                SELECT 1
+                FROM checklist_access mine
+                JOIN checklist_access theirs
+                ON theirs.checklist = mine.checklist
+                WHERE mine.argent_user = $1 AND theirs.argent_user = $2
+            )
+            OR EXISTS (
+                SELECT 1
+                FROM wishlist_access
+                WHERE (wishlist_user = $1 AND access_user = $2)
+                    OR (wishlist_user = $2 AND access_user = $1)
+            )",
+        )
+        .bind(viewer)
+        .bind(avatar_user)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    pub async fn get_avatar(&mut self, user_id: Uuid) -> ArgentResult<Avatar> {
+        query_as(
+            "SELECT
+                    content_type,
+                    image,
+                    version
+                FROM user_avatars
+                WHERE argent_user = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *self.db)
+        .await?
+        .ok_or_else(ArgentError::not_found)
+    }
+
+    /// Replaces any earlier avatar, returns the new version
+    pub async fn set_avatar(
+        &mut self,
+        user_id: Uuid,
+        content_type: &str,
+        image: &[u8],
+    ) -> ArgentResult<i64> {
+        let row = query(
+            "INSERT INTO user_avatars (
+                argent_user,
+                content_type,
+                image,
+                version
+            )
+            VALUES ($1, $2, $3, (EXTRACT(EPOCH FROM clock_timestamp()) * 1000)::BIGINT)
+            ON CONFLICT (argent_user)
+            DO UPDATE SET content_type = EXCLUDED.content_type,
+                image = EXCLUDED.image,
+                version = GREATEST(EXCLUDED.version, user_avatars.version + 1)
+            RETURNING version",
+        )
+        .bind(user_id)
+        .bind(content_type)
+        .bind(image)
+        .fetch_one(&mut *self.db)
+        .await?;
+        Ok(row.try_get("version")?)
+    }
+
+    /// Returns false if the user had no avatar
+    pub async fn delete_avatar(&mut self, user_id: Uuid) -> ArgentResult<bool> {
+        let result = query(
+            "DELETE FROM user_avatars
+            WHERE argent_user = $1",
+        )
+        .bind(user_id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ProfileStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(ProfileStore { db })
+    }
+}
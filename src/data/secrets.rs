@@ -0,0 +1,44 @@
+//! Random tokens handed out to clients. Only their hashes are stored, so a
+//! leaked database does not leak working tokens.
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// A url safe token made of `bytes` random bytes
+pub fn random_token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    base64::encode_config(buffer, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn hash_token(token: &str) -> String {
+    base64::encode_config(Sha256::digest(token.as_bytes()), base64::URL_SAFE_NO_PAD)
+}
+
+/// Encrypts `token` so that only whoever holds the token `key` can open it
+/// again, without storing anything that opens it on its own
+pub fn seal_token(token: &str, key: &str) -> Option<String> {
+    let bytes = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+    Some(base64::encode_config(
+        xor_with_key(bytes, key),
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+pub fn open_token(sealed: &str, key: &str) -> Option<String> {
+    seal_token(sealed, key)
+}
+
+/// XORs the bytes with a key stream made of hashes of `key` and a counter
+fn xor_with_key(mut bytes: Vec<u8>, key: &str) -> Vec<u8> {
+    for (block, chunk) in bytes.chunks_mut(32).enumerate() {
+        let stream = Sha256::new()
+            .chain_update(b"argent-seal")
+            .chain_update(key.as_bytes())
+            .chain_update((block as u64).to_be_bytes())
+            .finalize();
+        for (byte, key_byte) in chunk.iter_mut().zip(stream) {
+            *byte ^= key_byte;
+        }
+    }
+    bytes
+}
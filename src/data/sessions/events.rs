@@ -0,0 +1,25 @@
+use uuid::Uuid;
+
+use crate::data::broadcast::Broadcast;
+
+pub type SessionBroadcast = Broadcast<SessionRevoked>;
+
+/// Sessions that were revoked, so that streams opened with them end
+#[derive(Clone, Copy, Debug)]
+pub enum SessionRevoked {
+    Session(Uuid),
+    /// Every session of the user, as in logging out everywhere
+    AllOf(Uuid),
+}
+
+impl SessionRevoked {
+    /// Whether this ends `session` of `argent_user`. API tokens have no
+    /// session and are not ended by revoking sessions.
+    pub fn ends(&self, session: Option<Uuid>, argent_user: Uuid) -> bool {
+        match (*self, session) {
+            (SessionRevoked::Session(revoked), Some(session)) => revoked == session,
+            (SessionRevoked::AllOf(user), Some(_)) => user == argent_user,
+            (_, None) => false,
+        }
+    }
+}
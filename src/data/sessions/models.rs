@@ -0,0 +1,52 @@
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::data::secrets::{hash_token, random_token};
+
+const TOKEN_BYTES: usize = 32;
+
+/// One login on one device. Times are in milliseconds since the unix epoch.
+#[derive(Serialize, FromRow, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub created_at: i64,
+    pub last_seen: i64,
+    /// Whether this is the session making the request
+    #[sqlx(default)]
+    pub current: bool,
+}
+
+/// A newly issued refresh token, `token` goes to the client and only
+/// `token_hash` is stored.
+pub struct RefreshToken {
+    pub token: String,
+    pub token_hash: String,
+    pub session: Uuid,
+    pub argent_user: Uuid,
+}
+
+impl RefreshToken {
+    pub fn new(session: Uuid, argent_user: Uuid) -> Self {
+        let token = random_token(TOKEN_BYTES);
+        Self {
+            token_hash: hash_token(&token),
+            token,
+            session,
+            argent_user,
+        }
+    }
+}
+
+/// What presenting a refresh token led to
+pub enum Rotation {
+    /// The token was valid and is now used up, this successor belongs to the
+    /// same session. Presenting it again shortly after gives the same successor.
+    Rotated(RefreshToken),
+    /// The token had already been rotated, its session is now revoked
+    Reused { session: Uuid },
+    /// Unknown, expired or belonging to a revoked session
+    Invalid,
+}
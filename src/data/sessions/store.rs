@@ -0,0 +1,295 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{pool::PoolConnection, query, query_as, Acquire, PgConnection, Postgres, Row};
+use uuid::Uuid;
+
+use crate::api::helpers::ArgentResult;
+use crate::data::{
+    secrets::{hash_token, open_token, seal_token},
+    ArgentDB,
+};
+
+use super::models::{RefreshToken, Rotation, Session};
+
+/// How long a refresh token can be used, every rotation starts over
+pub const REFRESH_TOKEN_DAYS: i32 = 30;
+
+/// How long after its use a refresh token still gives the same successor
+const REUSE_GRACE_SECONDS: i32 = 30;
+
+pub struct SessionStore {
+    db: Connection<ArgentDB>,
+}
+
+impl SessionStore {
+    pub async fn create_session(
+        &mut self,
+        argent_user: Uuid,
+        user_agent: Option<&str>,
+    ) -> ArgentResult<Uuid> {
+        Self::create_session_conn(&mut self.db, argent_user, user_agent).await
+    }
+
+    pub async fn create_session_conn(
+        conn: &mut PoolConnection<Postgres>,
+        argent_user: Uuid,
+        user_agent: Option<&str>,
+    ) -> ArgentResult<Uuid> {
+        let session = Uuid::new_v4();
+        query(
+            "INSERT INTO sessions (
+                id,
+                argent_user,
+                user_agent
+            )
+            VALUES ($1, $2, $3)",
+        )
+        .bind(session)
+        .bind(argent_user)
+        .bind(user_agent)
+        .execute(&mut *conn)
+        .await?;
+        Ok(session)
+    }
+
+    /// Sessions that have not been revoked, most recently used first
+    pub async fn get_active_sessions(&mut self, argent_user: Uuid) -> ArgentResult<Vec<Session>> {
+        let sessions = query_as(
+            "SELECT
+                    id,
+                    user_agent,
+                    (EXTRACT(EPOCH FROM created_at) * 1000)::BIGINT AS created_at,
+                    (EXTRACT(EPOCH FROM last_seen) * 1000)::BIGINT AS last_seen
+                FROM sessions
+                WHERE argent_user = $1 AND revoked_at IS NULL
+                ORDER BY last_seen DESC",
+        )
+        .bind(argent_user)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(sessions)
+    }
+
+    /// Returns false if the user has no such active session
+    pub async fn revoke_session(&mut self, session: Uuid, argent_user: Uuid) -> ArgentResult<bool> {
+        let result = query(
+            "UPDATE sessions
+            SET revoked_at = now()
+            WHERE id = $1 AND argent_user = $2 AND revoked_at IS NULL",
+        )
+        .bind(session)
+        .bind(argent_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn revoke_all_sessions(&mut self, argent_user: Uuid) -> ArgentResult<()> {
+        query(
+            "UPDATE sessions
+            SET revoked_at = now()
+            WHERE argent_user = $1 AND revoked_at IS NULL",
+        )
+        .bind(argent_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks the session as seen, returns false if it is unknown or revoked.
+    /// `last_seen` is only written once a minute to spare the database.
+    pub async fn touch_session_conn(
+        conn: &mut PoolConnection<Postgres>,
+        session: Uuid,
+        argent_user: Uuid,
+    ) -> ArgentResult<bool> {
+        let active = query(
+            "SELECT last_seen < now() - INTERVAL '1 minute' AS stale
+            FROM sessions
+            WHERE id = $1 AND argent_user = $2 AND revoked_at IS NULL",
+        )
+        .bind(session)
+        .bind(argent_user)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let stale = match active {
+            Some(row) => row.try_get::<bool, _>("stale")?,
+            None => return Ok(false),
+        };
+        if stale {
+            query("UPDATE sessions SET last_seen = now() WHERE id = $1")
+                .bind(session)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(true)
+    }
+
+    pub async fn add_refresh_token(&mut self, token: &RefreshToken) -> ArgentResult<()> {
+        Self::add_refresh_token_conn(&mut self.db, token).await
+    }
+
+    async fn add_refresh_token_conn(
+        conn: &mut PgConnection,
+        token: &RefreshToken,
+    ) -> ArgentResult<()> {
+        query(
+            "INSERT INTO refresh_tokens (
+                token_hash,
+                session,
+                argent_user,
+                expires_at
+            )
+            VALUES ($1, $2, $3, now() + $4 * INTERVAL '1 day')",
+        )
+        .bind(&token.token_hash)
+        .bind(token.session)
+        .bind(token.argent_user)
+        .bind(REFRESH_TOKEN_DAYS)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Uses up the refresh token. A token that was already used means it was
+    /// copied, so its session is revoked for the thief and the owner alike.
+    /// Within `REUSE_GRACE_SECONDS` of its use it is a retry or another tab
+    /// refreshing at the same time instead, which get the same successor.
+    pub async fn rotate(&mut self, token: &str) -> ArgentResult<Rotation> {
+        let token_hash = hash_token(token);
+        let mut tx = self.db.begin().await?;
+        let used = query(
+            "SELECT refresh_tokens.session, refresh_tokens.argent_user
+            FROM refresh_tokens
+            JOIN sessions ON sessions.id = refresh_tokens.session
+            WHERE refresh_tokens.token_hash = $1
+                AND refresh_tokens.used_at IS NULL
+                AND refresh_tokens.expires_at > now()
+                AND sessions.revoked_at IS NULL
+            FOR UPDATE OF refresh_tokens",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(row) = used {
+            let successor = RefreshToken::new(row.try_get("session")?, row.try_get("argent_user")?);
+            query(
+                "UPDATE refresh_tokens
+                SET used_at = now(), successor = $2
+                WHERE token_hash = $1",
+            )
+            .bind(&token_hash)
+            .bind(seal_token(&successor.token, token))
+            .execute(&mut *tx)
+            .await?;
+            Self::add_refresh_token_conn(&mut tx, &successor).await?;
+            tx.commit().await?;
+            return Ok(Rotation::Rotated(successor));
+        }
+        tx.commit().await?;
+
+        let retried = query(
+            "SELECT refresh_tokens.session, refresh_tokens.argent_user, refresh_tokens.successor
+            FROM refresh_tokens
+            JOIN sessions ON sessions.id = refresh_tokens.session
+            WHERE refresh_tokens.token_hash = $1
+                AND refresh_tokens.used_at > now() - $2 * INTERVAL '1 second'
+                AND sessions.revoked_at IS NULL",
+        )
+        .bind(&token_hash)
+        .bind(REUSE_GRACE_SECONDS)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        if let Some(row) = retried {
+            let successor = row
+                .try_get::<Option<String>, _>("successor")?
+                .and_then(|sealed| open_token(&sealed, token));
+            if let Some(successor) = successor {
+                return Ok(Rotation::Rotated(RefreshToken {
+                    token_hash: hash_token(&successor),
+                    token: successor,
+                    session: row.try_get("session")?,
+                    argent_user: row.try_get("argent_user")?,
+                }));
+            }
+        }
+
+        let reused = query(
+            "UPDATE sessions
+            SET revoked_at = now()
+            FROM refresh_tokens
+            WHERE refresh_tokens.token_hash = $1
+                AND refresh_tokens.used_at IS NOT NULL
+                AND sessions.id = refresh_tokens.session
+                AND sessions.revoked_at IS NULL
+            RETURNING sessions.id",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        match reused {
+            Some(row) => Ok(Rotation::Reused {
+                session: row.try_get("id")?,
+            }),
+            None => Ok(Rotation::Invalid),
+        }
+    }
+
+    /// Revokes the session the refresh token belongs to, used at logout.
+    /// Returns the session unless it was unknown or revoked already.
+    pub async fn revoke_session_for_token(
+        &mut self,
+        token_hash: &str,
+    ) -> ArgentResult<Option<Uuid>> {
+        let revoked = query(
+            "UPDATE sessions
+            SET revoked_at = now()
+            FROM refresh_tokens
+            WHERE refresh_tokens.token_hash = $1
+                AND sessions.id = refresh_tokens.session
+                AND sessions.revoked_at IS NULL
+            RETURNING sessions.id",
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(revoked.map(|row| row.try_get("id")).transpose()?)
+    }
+
+    /// Whether the refresh token of `session` can still be rotated
+    pub async fn refresh_token_is_active_conn(
+        conn: &mut PoolConnection<Postgres>,
+        token_hash: &str,
+        session: Uuid,
+    ) -> ArgentResult<bool> {
+        let token = query(
+            "SELECT refresh_tokens.token_hash
+            FROM refresh_tokens
+            JOIN sessions ON sessions.id = refresh_tokens.session
+            WHERE refresh_tokens.token_hash = $1
+                AND refresh_tokens.session = $2
+                AND refresh_tokens.used_at IS NULL
+                AND refresh_tokens.expires_at > now()
+                AND sessions.revoked_at IS NULL",
+        )
+        .bind(token_hash)
+        .bind(session)
+        .fetch_optional(&mut *conn)
+        .await?;
+        Ok(token.is_some())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(SessionStore { db })
+    }
+}
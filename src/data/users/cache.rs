@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use super::models::User;
+
+/// Long enough to spare the database on bursts of requests, short enough
+/// that changes made on another server show up quickly
+pub const USER_CACHE_TTL: Duration = Duration::from_secs(30);
+
+struct CachedUser {
+    user: User,
+    fetched: Instant,
+}
+
+/// Users resolved by `AuthenticatedUser`. `UsersStore` invalidates the
+/// entries of users it updates or deletes.
+#[derive(Clone)]
+pub struct UserCache {
+    users: Arc<RwLock<HashMap<Uuid, CachedUser>>>,
+    ttl: Duration,
+}
+
+impl UserCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            users: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .get(&id)
+            .filter(|cached| cached.fetched.elapsed() < self.ttl)
+            .map(|cached| cached.user.clone())
+    }
+
+    pub fn insert(&self, user: User) {
+        let cached = CachedUser {
+            user,
+            fetched: Instant::now(),
+        };
+        let mut users = self.users.write().unwrap();
+        users.retain(|_, cached| cached.fetched.elapsed() < self.ttl);
+        users.insert(cached.user.id, cached);
+    }
+
+    pub fn invalidate(&self, id: Uuid) {
+        self.users.write().unwrap().remove(&id);
+    }
+}
+
+impl Default for UserCache {
+    fn default() -> Self {
+        Self::new(USER_CACHE_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::users::models::UserRole;
+
+    fn user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            name: String::from("user"),
+            email: String::from("user@test.argent"),
+            role: UserRole::User,
+        }
+    }
+
+    #[test]
+    fn returns_cached_users_until_invalidated() {
+        let cache = UserCache::default();
+        let user = user();
+        cache.insert(user.clone());
+        assert_eq!(
+            cache.get(user.id).map(|cached| cached.name),
+            Some(user.name)
+        );
+
+        cache.invalidate(user.id);
+        assert!(cache.get(user.id).is_none());
+    }
+
+    #[test]
+    fn expired_users_are_not_returned() {
+        let cache = UserCache::new(Duration::ZERO);
+        let user = user();
+        cache.insert(user.clone());
+        assert!(cache.get(user.id).is_none());
+    }
+}
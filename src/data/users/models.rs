@@ -3,7 +3,9 @@ use rocket::serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Type, Clone, Debug)]
+use crate::error::ArgentError;
+
+#[derive(Serialize, Deserialize, Type, Clone, Debug, PartialEq)]
 #[sqlx(type_name = "TEXT")]
 pub enum UserRole {
     Admin,
@@ -18,16 +20,69 @@ pub struct User {
     pub role: UserRole,
 }
 
-#[derive(Serialize)]
-pub struct UserForSharing {
-    id: Uuid,
-    name: String,
+#[derive(Deserialize)]
+pub struct NewUserRequest {
+    pub name: String,
+    pub email: String,
+    pub role: UserRole,
+}
+
+impl NewUserRequest {
+    pub fn get(self) -> Result<User, ArgentError> {
+        let user = User {
+            id: Uuid::new_v4(),
+            name: self.name,
+            email: self.email,
+            role: self.role,
+        };
+        user.validate()?;
+        Ok(user)
+    }
+}
+
+/// Fields left out stay as they are
+#[derive(Deserialize)]
+pub struct UserUpdateRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub role: Option<UserRole>,
 }
-impl UserForSharing {
-    pub fn from_user(user: User) -> Self {
-        UserForSharing {
-            id: user.id,
-            name: user.name,
+
+/// What users may change about themselves
+#[derive(Deserialize)]
+pub struct ProfileUpdateRequest {
+    pub name: Option<String>,
+}
+
+impl User {
+    pub fn update_from_request(self, request: UserUpdateRequest) -> Result<User, ArgentError> {
+        let user = User {
+            id: self.id,
+            name: request.name.unwrap_or(self.name),
+            email: request.email.unwrap_or(self.email),
+            role: request.role.unwrap_or(self.role),
+        };
+        user.validate()?;
+        Ok(user)
+    }
+
+    fn validate(&self) -> Result<(), ArgentError> {
+        if self.name.trim().is_empty() {
+            return Err(ArgentError::bad_request_msg("name cannot be empty"));
+        }
+        if !self.email.contains('@') {
+            return Err(ArgentError::bad_request_msg("email is not valid"));
         }
+        Ok(())
     }
 }
+
+/// `avatar_url` is absent for users without an avatar, it contains the avatar
+/// version so that the url changes whenever the avatar does
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UserForSharing {
+    id: Uuid,
+    name: String,
+    avatar_url: Option<String>,
+}
@@ -1,20 +1,70 @@
 use std::convert::Infallible;
 
-use rocket::request::FromRequest;
+use rocket::{http::Status, request::FromRequest};
 use rocket_db_pools::Connection;
-use sqlx::{pool::PoolConnection, query, query_as, Postgres};
+use sqlx::{pool::PoolConnection, query, query_as, Acquire, Postgres, Row, Transaction};
 use uuid::Uuid;
 
-use crate::data::users::models::User;
+use crate::data::users::{
+    cache::UserCache,
+    models::{User, UserForSharing, UserRole},
+};
 use crate::data::ArgentDB;
 use crate::{api::helpers::ArgentResult, error::ArgentError};
 
+/// Locks the admins until the transaction ends and refuses if `user_id`
+/// is the only one, so that some admin is always left
+async fn check_other_admin_remains(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> ArgentResult<()> {
+    let admins = query(
+        "SELECT id
+            FROM argent_users
+            WHERE role = $1
+            FOR UPDATE",
+    )
+    .bind(UserRole::Admin)
+    .fetch_all(&mut *tx)
+    .await?
+    .iter()
+    .map(|row| row.try_get::<Uuid, _>("id"))
+    .collect::<Result<Vec<_>, _>>()?;
+    if admins.contains(&user_id) && admins.len() == 1 {
+        Err(ArgentError::new(
+            "cannot remove the last admin",
+            Status::Conflict,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 pub struct UsersStore {
     pub(crate) db: Connection<ArgentDB>,
+    cache: UserCache,
 }
 
 impl UsersStore {
-    pub async fn get_user_for_email(&mut self, email: &str) -> Result<User, ArgentError> {
+    pub async fn has_user_for_email(
+        conn: &mut PoolConnection<Postgres>,
+        email: &str,
+    ) -> Result<bool, ArgentError> {
+        let user = query(
+            "SELECT
+                    id,
+                    email
+                FROM argent_users
+                WHERE lower(email) = lower($1)",
+        )
+        .bind(email)
+        .fetch_optional(&mut *conn)
+        .await
+        .unwrap();
+        Ok(user.is_some())
+    }
+
+    pub async fn get_user(&mut self, id: Uuid) -> Result<User, ArgentError> {
         let user = query_as(
             "SELECT
                     id,
@@ -22,33 +72,39 @@ impl UsersStore {
                     email,
                     role
                 FROM argent_users
-                WHERE email = $1",
+                WHERE id = $1",
         )
-        .bind(email)
+        .bind(id)
         .fetch_one(&mut *self.db)
         .await?;
         Ok(user)
     }
 
-    pub async fn has_user_for_email(
-        conn: &mut PoolConnection<Postgres>,
-        email: &str,
-    ) -> Result<bool, ArgentError> {
-        let user = query(
+    pub async fn find_user_for_email(&mut self, email: &str) -> ArgentResult<Option<User>> {
+        let user = query_as(
             "SELECT
                     id,
-                    email
+                    name,
+                    email,
+                    role
                 FROM argent_users
-                WHERE email = $1",
+                WHERE lower(email) = lower($1)",
         )
         .bind(email)
-        .fetch_optional(&mut *conn)
-        .await
-        .unwrap();
-        Ok(user.is_some())
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(user)
     }
 
-    pub async fn get_user(&mut self, id: Uuid) -> Result<User, ArgentError> {
+    pub async fn find_user(&mut self, id: Uuid) -> ArgentResult<Option<User>> {
+        Self::find_user_conn(&mut self.db, id).await
+    }
+
+    /// Used by `AuthenticatedUser`, which may only hold one connection
+    pub async fn find_user_conn(
+        conn: &mut PoolConnection<Postgres>,
+        id: Uuid,
+    ) -> ArgentResult<Option<User>> {
         let user = query_as(
             "SELECT
                     id,
@@ -59,7 +115,7 @@ impl UsersStore {
                 WHERE id = $1",
         )
         .bind(id)
-        .fetch_one(&mut *self.db)
+        .fetch_optional(&mut *conn)
         .await?;
         Ok(user)
     }
@@ -78,6 +134,33 @@ impl UsersStore {
         Ok(users)
     }
 
+    /// Everyone who shares a group with `user_id`, including that user when in a group
+    pub async fn get_all_for_sharing(
+        &mut self,
+        user_id: Uuid,
+    ) -> ArgentResult<Vec<UserForSharing>> {
+        let users = query_as(
+            "SELECT
+                id,
+                name,
+                '/api/v1/users/' || u.id || '/avatar?v=' || av.version AS avatar_url
+            FROM argent_users u
+            LEFT JOIN user_avatars av
+            ON av.argent_user = u.id
+            WHERE u.id IN (
+                SELECT theirs.argent_user
+                FROM group_members mine
+                JOIN group_members theirs
+                ON theirs.group_id = mine.group_id
+                WHERE mine.argent_user = $1
+            )",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(users)
+    }
+
     pub async fn add_user_conn(
         conn: &mut PoolConnection<Postgres>,
         user: User,
@@ -105,7 +188,55 @@ impl UsersStore {
         Self::add_user_conn(&mut *self.db, user).await
     }
 
+    /// Refused if it would demote the last admin
+    pub async fn update_user(&mut self, user: &User) -> ArgentResult<()> {
+        let mut tx = self.db.begin().await?;
+        if user.role != UserRole::Admin {
+            check_other_admin_remains(&mut tx, user.id).await?;
+        }
+        query(
+            "UPDATE argent_users
+            SET name = $2,
+                email = $3,
+                role = $4
+            WHERE id = $1",
+        )
+        .bind(user.id)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(&user.role)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.cache.invalidate(user.id);
+        Ok(())
+    }
+
+    /// Only changes the name, so a stale copy of the user cannot undo
+    /// changes to the email or role
+    pub async fn update_name(&mut self, user_id: Uuid, name: &str) -> ArgentResult<User> {
+        let user = query_as(
+            "UPDATE argent_users
+            SET name = $2
+            WHERE id = $1
+            RETURNING
+                id,
+                name,
+                email,
+                role",
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_one(&mut *self.db)
+        .await?;
+        self.cache.invalidate(user_id);
+        Ok(user)
+    }
+
+    /// Refused if it would delete the last admin
     pub async fn delete_user(&mut self, user_id: Uuid) -> ArgentResult<()> {
+        let mut tx = self.db.begin().await?;
+        check_other_admin_remains(&mut tx, user_id).await?;
         query(
             "
             DELETE FROM argent_users
@@ -113,9 +244,11 @@ impl UsersStore {
         ",
         )
         .bind(user_id)
-        .execute(&mut *self.db)
+        .execute(&mut *tx)
         .await
         .map(|_| ())?;
+        tx.commit().await?;
+        self.cache.invalidate(user_id);
         Ok(())
     }
 }
@@ -128,6 +261,7 @@ impl<'r> FromRequest<'r> for UsersStore {
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
         let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
-        rocket::request::Outcome::Success(UsersStore { db })
+        let cache = request.rocket().state::<UserCache>().unwrap().clone();
+        rocket::request::Outcome::Success(UsersStore { db, cache })
     }
 }
@@ -0,0 +1,90 @@
+use rocket::serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct WishlistItemRequest {
+    title: String,
+    description: String,
+}
+
+/// Database row for a wish. Never serialized directly since `taken_by`
+/// must stay hidden from the wishlist owner, use the view models below.
+#[derive(FromRow)]
+pub struct WishlistItem {
+    pub id: Uuid,
+    pub title: String,
+    pub description: String,
+    pub argent_user: Uuid,
+    pub taken_by: Option<Uuid>,
+}
+
+impl WishlistItem {
+    pub fn from_request(request: WishlistItemRequest, user_id: Uuid) -> WishlistItem {
+        WishlistItem {
+            id: Uuid::new_v4(),
+            title: request.title,
+            description: request.description,
+            argent_user: user_id,
+            taken_by: None,
+        }
+    }
+
+    pub fn update_from_request(self, request: WishlistItemRequest) -> WishlistItem {
+        WishlistItem {
+            title: request.title,
+            description: request.description,
+            ..self
+        }
+    }
+}
+
+/// A wish as seen by the owner of the wishlist
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnWishlistItem {
+    id: Uuid,
+    title: String,
+    description: String,
+}
+
+impl OwnWishlistItem {
+    pub fn from_item(item: WishlistItem) -> Self {
+        OwnWishlistItem {
+            id: item.id,
+            title: item.title,
+            description: item.description,
+        }
+    }
+}
+
+/// A wish as seen by someone the owner has shared the wishlist with
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedWishlistItem {
+    id: Uuid,
+    title: String,
+    description: String,
+    argent_user: Uuid,
+    taken: bool,
+    taken_by_me: bool,
+}
+
+impl SharedWishlistItem {
+    pub fn for_viewer(item: WishlistItem, viewer: Uuid) -> Self {
+        SharedWishlistItem {
+            id: item.id,
+            title: item.title,
+            description: item.description,
+            argent_user: item.argent_user,
+            taken: item.taken_by.is_some(),
+            taken_by_me: item.taken_by == Some(viewer),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WishlistShareRequest {
+    pub user_id: String,
+}
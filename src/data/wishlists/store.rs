@@ -0,0 +1,240 @@
+use std::convert::Infallible;
+
+use rocket::request::FromRequest;
+use rocket_db_pools::Connection;
+use sqlx::{query, query_as};
+use uuid::Uuid;
+
+use crate::data::users::models::UserForSharing;
+use crate::data::ArgentDB;
+use crate::{api::helpers::ArgentResult, error::ArgentError};
+
+use super::models::WishlistItem;
+
+pub struct WishlistStore {
+    db: Connection<ArgentDB>,
+}
+
+impl WishlistStore {
+    pub async fn get_wishlist_items(
+        &mut self,
+        wishlist_user: Uuid,
+    ) -> ArgentResult<Vec<WishlistItem>> {
+        let items = query_as(
+            "SELECT
+                    id,
+                    title,
+                    description,
+                    argent_user,
+                    taken_by
+                FROM wishlist_items
+                WHERE argent_user = $1
+                ORDER BY title",
+        )
+        .bind(wishlist_user)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(items)
+    }
+
+    pub async fn get_item(&mut self, id: Uuid) -> ArgentResult<WishlistItem> {
+        let item = query_as(
+            "SELECT
+                    id,
+                    title,
+                    description,
+                    argent_user,
+                    taken_by
+                FROM wishlist_items
+                WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        item.ok_or_else(ArgentError::not_found)
+    }
+
+    pub async fn add_item(&mut self, item: WishlistItem) -> ArgentResult<()> {
+        query(
+            "INSERT INTO wishlist_items (
+                id,
+                title,
+                description,
+                argent_user
+            )
+            VALUES ($1, $2, $3, $4)",
+        )
+        .bind(item.id)
+        .bind(item.title)
+        .bind(item.description)
+        .bind(item.argent_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_item(&mut self, item: WishlistItem) -> ArgentResult<()> {
+        query(
+            "UPDATE wishlist_items
+            SET title = $1,
+                description = $2
+            WHERE id = $3",
+        )
+        .bind(item.title)
+        .bind(item.description)
+        .bind(item.id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Marks the item as taken by `user_id`, returns false if someone already took it
+    pub async fn claim_item(&mut self, id: Uuid, user_id: Uuid) -> ArgentResult<bool> {
+        let result = query(
+            "UPDATE wishlist_items
+            SET taken_by = $1
+            WHERE id = $2
+            AND taken_by IS NULL",
+        )
+        .bind(user_id)
+        .bind(id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Releases the item if it is taken by `user_id`, returns false otherwise
+    pub async fn unclaim_item(&mut self, id: Uuid, user_id: Uuid) -> ArgentResult<bool> {
+        let result = query(
+            "UPDATE wishlist_items
+            SET taken_by = NULL
+            WHERE id = $1
+            AND taken_by = $2",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn delete_item(&mut self, id: Uuid) -> ArgentResult<()> {
+        query(
+            "DELETE FROM wishlist_items
+            WHERE id = $1",
+        )
+        .bind(id)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn has_access(
+        &mut self,
+        wishlist_user: Uuid,
+        access_user: Uuid,
+    ) -> ArgentResult<bool> {
+        let row = query(
+            "SELECT wishlist_user
+                FROM wishlist_access
+                WHERE wishlist_user = $1
+                AND access_user = $2",
+        )
+        .bind(wishlist_user)
+        .bind(access_user)
+        .fetch_optional(&mut *self.db)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    /// Users that have given `access_user` access to their wishlist
+    pub async fn get_wishlists_for_user(
+        &mut self,
+        access_user: Uuid,
+    ) -> ArgentResult<Vec<UserForSharing>> {
+        let users = query_as(
+            "SELECT
+                id,
+                name,
+                '/api/v1/users/' || u.id || '/avatar?v=' || av.version AS avatar_url
+            FROM argent_users u
+            LEFT JOIN wishlist_access wa
+            ON wa.wishlist_user = u.id
+            LEFT JOIN user_avatars av
+            ON av.argent_user = u.id
+            WHERE wa.access_user = $1",
+        )
+        .bind(access_user)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(users)
+    }
+
+    /// Users that can see the wishlist of `wishlist_user`
+    pub async fn get_users_with_access(
+        &mut self,
+        wishlist_user: Uuid,
+    ) -> ArgentResult<Vec<UserForSharing>> {
+        let users = query_as(
+            "SELECT
+                id,
+                name,
+                '/api/v1/users/' || u.id || '/avatar?v=' || av.version AS avatar_url
+            FROM argent_users u
+            LEFT JOIN wishlist_access wa
+            ON wa.access_user = u.id
+            LEFT JOIN user_avatars av
+            ON av.argent_user = u.id
+            WHERE wa.wishlist_user = $1",
+        )
+        .bind(wishlist_user)
+        .fetch_all(&mut *self.db)
+        .await?;
+        Ok(users)
+    }
+
+    pub async fn add_access(&mut self, wishlist_user: Uuid, access_user: Uuid) -> ArgentResult<()> {
+        query(
+            "INSERT INTO wishlist_access (
+                wishlist_user,
+                access_user
+            )
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(wishlist_user)
+        .bind(access_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_access(
+        &mut self,
+        wishlist_user: Uuid,
+        access_user: Uuid,
+    ) -> ArgentResult<()> {
+        query(
+            "DELETE FROM wishlist_access
+            WHERE wishlist_user = $1
+            AND access_user = $2",
+        )
+        .bind(wishlist_user)
+        .bind(access_user)
+        .execute(&mut *self.db)
+        .await?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WishlistStore {
+    type Error = Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let db = request.guard::<Connection<ArgentDB>>().await.unwrap();
+        rocket::request::Outcome::Success(WishlistStore { db })
+    }
+}
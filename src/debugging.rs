@@ -1,70 +1,266 @@
+use std::collections::HashSet;
+
 use rocket::{
+    figment::{
+        providers::{Format, Toml},
+        Figment,
+    },
     log::private::{error, info},
+    serde::Deserialize,
     Build, Rocket,
 };
 use rocket_db_pools::Database;
+use sqlx::{pool::PoolConnection, types::time::OffsetDateTime, Postgres};
+use uuid::Uuid;
 
-use crate::data::{
-    users::{
-        models::{User, UserRole},
-        store::UsersStore,
+use crate::{
+    api::helpers::ArgentResult,
+    config::DevConfig,
+    data::{
+        checklists::{
+            models::{Checklist, ChecklistItem},
+            store::ChecklistStore,
+        },
+        groups::{
+            models::{Group, GroupRole},
+            store::GroupStore,
+        },
+        users::{
+            models::{User, UserRole},
+            store::UsersStore,
+        },
+        ArgentDB,
     },
-    ArgentDB,
 };
 
-pub fn load_debug_env() {
-    if cfg!(debug_assertions) {
-        info!("loading debug environment...");
-        std::fs::read_to_string("./debug.env")
-            .unwrap()
-            .split('\n')
-            .for_each(|it| {
-                let kv = if it.trim().is_empty() || it.starts_with('#') {
-                    None
+/// Users and checklists for developing without the identity provider.
+/// Seeding is repeatable, users that exist are left alone together with
+/// their checklists.
+#[derive(Deserialize)]
+pub struct DevSeed {
+    /// The group seeded users join so they can share with each other
+    #[serde(default = "everyone")]
+    pub group: String,
+    #[serde(default)]
+    pub users: Vec<SeedUser>,
+    #[serde(default)]
+    pub checklists: Vec<SeedChecklist>,
+}
+
+#[derive(Deserialize)]
+pub struct SeedUser {
+    pub email: String,
+    pub name: String,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SeedChecklist {
+    /// Email of one of the seeded users
+    pub owner: String,
+    pub name: String,
+    #[serde(default)]
+    pub items: Vec<String>,
+}
+
+/// Emails of the users in the seed file, dev login only works for them
+#[derive(Default)]
+pub struct DevUsers(HashSet<String>);
+
+impl DevUsers {
+    pub fn from_seed(seed: &DevSeed) -> Self {
+        DevUsers(seed.users.iter().map(|user| user.email.clone()).collect())
+    }
+
+    pub fn contains(&self, email: &str) -> bool {
+        self.0.contains(email)
+    }
+}
+
+fn everyone() -> String {
+    String::from("Everyone")
+}
+
+impl DevSeed {
+    pub fn from_file(path: &str) -> Result<DevSeed, String> {
+        let toml = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Figment::from(Toml::string(&toml))
+            .extract()
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Adds the users that do not exist yet and their checklists,
+    /// returns how many users were added
+    pub async fn apply(&self, conn: &mut PoolConnection<Postgres>) -> ArgentResult<usize> {
+        let mut added = Vec::new();
+        for seed_user in &self.users {
+            if UsersStore::has_user_for_email(conn, &seed_user.email).await? {
+                continue;
+            }
+            let user = User {
+                id: Uuid::new_v4(),
+                email: seed_user.email.clone(),
+                name: seed_user.name.clone(),
+                role: if seed_user.admin {
+                    UserRole::Admin
                 } else {
-                    it.split_once('=')
+                    UserRole::User
+                },
+            };
+            UsersStore::add_user_conn(conn, user.clone()).await?;
+            added.push(user);
+        }
+        if added.is_empty() {
+            return Ok(0);
+        }
+
+        let group = match GroupStore::find_group_by_name_conn(conn, &self.group).await? {
+            Some(group) => group,
+            None => {
+                let group = Group {
+                    id: Uuid::new_v4(),
+                    name: self.group.clone(),
                 };
-                if let Some((key, value)) = kv {
-                    std::env::set_var(key, value);
-                }
-            })
+                GroupStore::create_group_conn(conn, &group, None).await?;
+                group
+            }
+        };
+        for user in &added {
+            let role = match user.role {
+                UserRole::Admin => GroupRole::Owner,
+                UserRole::User => GroupRole::Member,
+            };
+            GroupStore::set_member_conn(conn, group.id, user.id, role).await?;
+        }
+
+        for seed_checklist in &self.checklists {
+            let owner = match added.iter().find(|user| user.email == seed_checklist.owner) {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let checklist = Checklist {
+                id: Uuid::new_v4(),
+                name: seed_checklist.name.clone(),
+            };
+            let checklist_id = checklist.id;
+            ChecklistStore::create_checklist_conn(conn, checklist, owner.id).await?;
+            for title in &seed_checklist.items {
+                let item = ChecklistItem {
+                    id: Uuid::new_v4(),
+                    title: title.clone(),
+                    checklist: checklist_id,
+                    done: false,
+                    created_at: OffsetDateTime::now_utc().unix_timestamp(),
+                    // Set when the item is stored
+                    position: 0.0,
+                };
+                ChecklistStore::add_item_conn(conn, &item).await?;
+            }
+        }
+        Ok(added.len())
     }
 }
 
-pub async fn init_dev_admin(rocket: Rocket<Build>) -> Rocket<Build> {
-    if cfg!(debug_assertions) {
-        match ArgentDB::fetch(&rocket) {
-            Some(database) => match database.acquire().await {
-                Ok(mut conn) => {
-                    match UsersStore::has_user_for_email(&mut conn, "eric.o.berglund@gmail.com")
-                        .await
-                    {
-                        Ok(false) => {
-                            info!("Adding dev admin user");
-                            let res = UsersStore::add_user_conn(
-                                &mut conn,
-                                User {
-                                    id: uuid::Uuid::new_v4(),
-                                    email: String::from("eric.o.berglund@gmail.com"),
-                                    name: String::from("Eric"),
-                                    role: UserRole::Admin,
-                                },
-                            )
-                            .await;
-                            if let Err(err) = res {
-                                error!("Adding dev user: Db error when adding users: {}", err);
-                            }
-                        }
-                        Ok(true) => {}
-                        Err(err) => {
-                            error!("Adding dev user: Db error when checking for user: {}", err)
-                        }
-                    }
-                }
-                Err(err) => error!("Adding dev user: No database connection: {}", err),
-            },
-            None => error!("Adding dev user: No database"),
+pub async fn seed_dev_data(rocket: Rocket<Build>, config: DevConfig) -> Rocket<Build> {
+    if !cfg!(debug_assertions) {
+        return rocket;
+    }
+    let seed = match config.seed_file.as_deref().map(DevSeed::from_file) {
+        Some(Ok(seed)) => seed,
+        Some(Err(err)) => {
+            error!("Seeding dev data: Invalid seed file: {}", err);
+            return rocket.manage(DevUsers::default());
         }
+        None => return rocket.manage(DevUsers::default()),
+    };
+    match ArgentDB::fetch(&rocket) {
+        Some(database) => match database.acquire().await {
+            Ok(mut conn) => match seed.apply(&mut conn).await {
+                Ok(0) => {}
+                Ok(added) => info!("Seeded {} dev users", added),
+                Err(err) => error!("Seeding dev data: Db error: {}", err),
+            },
+            Err(err) => error!("Seeding dev data: No database connection: {}", err),
+        },
+        None => error!("Seeding dev data: No database"),
+    }
+    rocket.manage(DevUsers::from_seed(&seed))
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use rocket::{
+        figment::{
+            providers::{Format, Toml},
+            Figment,
+        },
+        http::Status,
     };
-    return rocket;
+    use uuid::Uuid;
+
+    use crate::{
+        data::users::models::User,
+        testing::{connection, get_as, TestApp},
+    };
+
+    use super::{DevSeed, DevUsers};
+
+    #[rocket::async_test]
+    async fn seeding_adds_users_and_checklists_once() {
+        let admin_email = format!("{}@test.argent", Uuid::new_v4());
+        let seed: DevSeed = Figment::from(Toml::string(&format!(
+            r#"
+            group = "Developers"
+
+            [[users]]
+            email = "{}"
+            name = "Admin"
+            admin = true
+
+            [[users]]
+            email = "{}@test.argent"
+            name = "User"
+
+            [[checklists]]
+            owner = "{}"
+            name = "Groceries"
+            items = ["Milk", "Bread"]
+            "#,
+            admin_email,
+            Uuid::new_v4(),
+            admin_email
+        )))
+        .extract()
+        .unwrap();
+        let app = TestApp::with_dev_users(DevUsers::from_seed(&seed)).await;
+        let client = app.client();
+
+        let mut conn = connection(client).await;
+        assert_eq!(seed.apply(&mut conn).await.unwrap(), 2);
+        assert_eq!(seed.apply(&mut conn).await.unwrap(), 0);
+
+        let response = client
+            .get(format!("/api/v1/auth/dev-login?email={}", admin_email))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let admin: User = response.into_json().await.unwrap();
+        let (status, checklists) = get_as(client, &admin, "/api/v1/checklists").await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(checklists.as_array().unwrap().len(), 1);
+        assert_eq!(checklists[0]["name"], "Groceries");
+        let (_, items) = get_as(
+            client,
+            &admin,
+            &format!(
+                "/api/v1/checklists/{}/items",
+                checklists[0]["id"].as_str().unwrap()
+            ),
+        )
+        .await;
+        assert_eq!(items.as_array().unwrap().len(), 2);
+        let (_, users) = get_as(client, &admin, "/api/v1/users").await;
+        assert_eq!(users.as_array().unwrap().len(), 2);
+    }
 }
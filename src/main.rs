@@ -3,9 +3,12 @@ pub mod data;
 pub mod api {
     pub mod auth {
         pub mod cookie;
+        pub mod csrf;
         pub mod google_verification;
         pub mod jwk;
         pub mod jwt;
+        pub mod keyring;
+        pub mod session_renewal;
         pub mod user_guard;
     }
     pub mod helpers;
@@ -15,15 +18,25 @@ pub mod config;
 pub mod cors;
 pub mod debugging;
 pub mod error;
+#[cfg(test)]
+mod testing;
 
-use crate::{api::v1::ApiV1Routes, data::ArgentDB};
-use api::auth::jwk::Jwks;
-use config::AuthenticationConfig;
+use crate::{
+    api::v1::ApiV1Routes,
+    data::{
+        chat::models::ChatBroadcast, checklists::events::ChecklistBroadcast,
+        sessions::events::SessionBroadcast, users::cache::UserCache, ArgentDB,
+    },
+};
+use api::auth::{jwk::Jwks, session_renewal::SessionRenewal};
+use config::{ArgentConfig, AuthenticationConfig};
 use cors::CORS;
 use data::run_migrations;
-use debugging::{init_dev_admin, load_debug_env};
+use debugging::seed_dev_data;
 use error::SimpleMessage;
-use rocket::{fairing::AdHoc, get, launch, routes, serde::json::Json};
+use rocket::{
+    fairing::AdHoc, figment::Figment, get, launch, routes, serde::json::Json, Build, Rocket,
+};
 use rocket_db_pools::Database;
 
 //#[macro_use]
@@ -39,23 +52,48 @@ async fn ping() -> Json<SimpleMessage> {
     Json(SimpleMessage::ok())
 }
 
-#[launch]
-async fn rocket() -> _ {
-    load_debug_env();
-    rocket::build()
+/// The application with everything that depends on the environment passed in
+pub fn build_rocket(
+    figment: Figment,
+    auth_config: AuthenticationConfig,
+    jwks: Jwks,
+) -> Rocket<Build> {
+    let cors = CORS::new(auth_config.allowed_origins.clone());
+    rocket::custom(figment)
         .attach(ArgentDB::init())
         .attach(AdHoc::try_on_ignite("Migrate database", run_migrations))
-        .attach(AdHoc::on_ignite(
-            "Debug: init admin account",
-            init_dev_admin,
-        ))
-        .manage(
-            Jwks::new()
-                .await
-                .expect("Could not start google Jwt verifier"),
-        )
-        .manage(AuthenticationConfig::from_env())
-        .attach(CORS::init())
+        .manage(jwks)
+        .manage(auth_config)
+        .manage(UserCache::default())
+        .manage(ChatBroadcast::new())
+        .manage(ChecklistBroadcast::new())
+        .manage(SessionBroadcast::new())
+        .attach(SessionRenewal)
+        .attach(cors)
         .mount("/api/v1", ApiV1Routes::get())
         .mount("/", routes![ping, health_check])
 }
+
+#[launch]
+async fn rocket() -> _ {
+    let figment = ArgentConfig::figment();
+    let config = ArgentConfig::from_figment(&figment).unwrap_or_else(|errors| {
+        eprintln!("{}", errors);
+        std::process::exit(1)
+    });
+    let jwks = Jwks::new(config.idp).await;
+    let dev_config = config.dev;
+    build_rocket(figment, config.auth, jwks)
+        .attach(AdHoc::on_liftoff(
+            "Refresh identity provider keys",
+            |rocket| {
+                Box::pin(async move {
+                    let jwks = rocket.state::<Jwks>().unwrap().clone();
+                    rocket::tokio::spawn(jwks.refresh_periodically(rocket.shutdown()));
+                })
+            },
+        ))
+        .attach(AdHoc::on_ignite("Debug: seed dev data", |rocket| {
+            seed_dev_data(rocket, dev_config)
+        }))
+}